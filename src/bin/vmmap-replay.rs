@@ -0,0 +1,59 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::process::ExitCode;
+
+use rust_vmmap::strace::StraceReplay;
+
+const USAGE: &str = "usage: vmmap-replay [LOG]
+
+Replays the mmap, munmap, mprotect, mremap and brk calls of an `strace -f` log,
+read from LOG or standard input, against a vmmap. Prints the resulting map of
+every address space, then every call whose traced return value differs from
+what the vmmap would have returned, and every traced call the vmmap could not
+apply.";
+
+fn main() -> ExitCode {
+    let path = env::args().nth(1);
+    if matches!(path.as_deref(), Some("-h" | "--help")) {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+
+    let mut replay = StraceReplay::new();
+    let result = match path.as_deref() {
+        None | Some("-") => replay.replay(io::stdin().lock()),
+        Some(path) => File::open(path).and_then(|file| replay.replay(BufReader::new(file))),
+    };
+    if let Err(err) = result {
+        eprintln!("vmmap-replay: {err}");
+        return ExitCode::FAILURE;
+    }
+
+    for space in replay.address_spaces() {
+        let pids: Vec<String> = space.pids.iter().map(u64::to_string).collect();
+        println!("pid {}", pids.join(", "));
+        for line in space.maps() {
+            println!("{line}");
+        }
+        println!();
+    }
+
+    match replay.mismatches() {
+        [] => println!("no mismatches"),
+        mismatches => {
+            println!("mismatches: {}", mismatches.len());
+            for mismatch in mismatches {
+                println!("{mismatch}");
+            }
+        }
+    }
+    if !replay.failures().is_empty() {
+        println!();
+        println!("calls that could not be applied: {}", replay.failures().len());
+        for failure in replay.failures() {
+            println!("{failure}");
+        }
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,274 @@
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use nodit::interval::ie;
+
+use crate::constants::{ENOMEM, MAP_NORESERVE, MAP_SHARED, PROT_WRITE};
+use crate::store::EntryStore;
+use crate::types::OvercommitMode;
+use crate::vmmap::Vmmap;
+
+/// Commit charge of every cage sharing the registry, checked against a single
+/// limit the way the kernel accounts `Committed_AS` system wide
+#[derive(Debug)]
+pub struct CommitRegistry {
+    pub mode: OvercommitMode,
+    // In `Never` mode the most pages all cages may have committed, in `Heuristic`
+    // mode the largest single request that is not obviously bogus (RAM + swap)
+    pub commit_limit: u64,
+    committed: u64,
+}
+
+/// Handle cages keep on the registry they are charged against
+pub type SharedCommitRegistry = Arc<Mutex<CommitRegistry>>;
+
+impl CommitRegistry {
+    pub fn new(mode: OvercommitMode, commit_limit: u64) -> Self {
+        CommitRegistry {
+            mode,
+            commit_limit,
+            committed: 0,
+        }
+    }
+
+    pub fn shared(mode: OvercommitMode, commit_limit: u64) -> SharedCommitRegistry {
+        Arc::new(Mutex::new(Self::new(mode, commit_limit)))
+    }
+
+    /// Locks a shared registry. Its counters stay consistent even if a cage
+    /// panicked while holding it, so a poisoned lock is used as is.
+    fn lock(registry: &SharedCommitRegistry) -> MutexGuard<'_, CommitRegistry> {
+        registry.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Pages currently committed across all cages
+    pub fn committed(&self) -> u64 {
+        self.committed
+    }
+
+    /// Charges `npages` more pages, failing with ENOMEM if the mode refuses them
+    fn try_charge(&mut self, npages: u64) -> Result<(), io::Error> {
+        let refused = match self.mode {
+            OvercommitMode::Always => false,
+            OvercommitMode::Heuristic => npages > self.commit_limit,
+            OvercommitMode::Never => self.committed + npages > self.commit_limit,
+        };
+        if refused {
+            return Err(io::Error::from_raw_os_error(ENOMEM));
+        }
+        self.committed += npages;
+        Ok(())
+    }
+}
+
+/// Private writable mappings need memory the cage may write to at any time, so
+/// they are charged up front unless mapped with MAP_NORESERVE
+pub(crate) fn is_commit_charged(flags: i32, prot: i32) -> bool {
+    flags & (MAP_SHARED | MAP_NORESERVE) as i32 == 0 && prot & PROT_WRITE != 0
+}
+
+impl<S: EntryStore> Vmmap<S> {
+    /// Pages this vmmap has committed
+    pub fn commit_charge(&self) -> u64 {
+        self.commit_charge
+    }
+
+    /// Makes this vmmap charge `registry` from now on, moving its current charge
+    /// over from the registry it used before, if any
+    pub fn set_commit_registry(&mut self, registry: SharedCommitRegistry) {
+        if let Some(old) = &self.commit_registry {
+            let mut old = CommitRegistry::lock(old);
+            old.committed = old.committed.saturating_sub(self.commit_charge);
+        }
+        CommitRegistry::lock(&registry).committed += self.commit_charge;
+        self.commit_registry = Some(registry);
+    }
+
+    /// Number of mapped pages of `[start_page, end_page)` that are charged, or
+    /// that would be once their protection is changed to `new_prot`
+    pub(crate) fn charged_pages(
+        &self,
+        start_page: u64,
+        end_page: u64,
+        new_prot: Option<i32>,
+    ) -> u64 {
+        if start_page >= end_page {
+            return 0;
+        }
+        self.entries
+            .overlapping(ie(start_page, end_page))
+            .filter(|(_, entry)| is_commit_charged(entry.flags, new_prot.unwrap_or(entry.prot)))
+            .map(|(interval, _)| {
+                end_page.min(interval.end() + 1) - start_page.max(interval.start())
+            })
+            .sum()
+    }
+
+    /// Replaces `old_pages` of charge by `new_pages`. Growing the charge can be
+    /// refused by the registry with ENOMEM, in which case nothing changes.
+    pub(crate) fn recharge(&mut self, old_pages: u64, new_pages: u64) -> Result<(), io::Error> {
+        // entries can be edited in place through `find_page_mut`, so `old_pages`
+        // may count pages that were never charged; never give back more than held
+        let old_pages = old_pages.min(self.commit_charge);
+        if let Some(registry) = &self.commit_registry {
+            let mut registry = CommitRegistry::lock(registry);
+            if new_pages > old_pages {
+                registry.try_charge(new_pages - old_pages)?;
+            } else {
+                registry.committed = registry.committed.saturating_sub(old_pages - new_pages);
+            }
+        }
+        self.commit_charge = self.commit_charge - old_pages + new_pages;
+        Ok(())
+    }
+
+    /// Puts the charge back to `pages` without consulting the overcommit mode,
+    /// used when rolling back to a state that was already accepted
+    pub(crate) fn restore_charge(&mut self, pages: u64) {
+        if let Some(registry) = &self.commit_registry {
+            let mut registry = CommitRegistry::lock(registry);
+            registry.committed = registry.committed.saturating_sub(self.commit_charge) + pages;
+        }
+        self.commit_charge = pages;
+    }
+}
+
+impl<S: EntryStore> Drop for Vmmap<S> {
+    fn drop(&mut self) {
+        if let Some(registry) = &self.commit_registry {
+            let mut registry = CommitRegistry::lock(registry);
+            registry.committed = registry.committed.saturating_sub(self.commit_charge);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{ENOMEM, MAP_NORESERVE, MAP_SHARED, PROT_READ, PROT_WRITE};
+    use crate::types::{MemoryBackingType, OvercommitMode, VmmapOps};
+    use crate::vmmap::Vmmap;
+    use crate::vmmap_entries::test_vmmap_entry_util::create_default_vmmap_entry;
+
+    use super::CommitRegistry;
+
+    fn map(vmmap: &mut Vmmap, page_num: u64, npages: u64, prot: i32, flags: i32) {
+        vmmap
+            .add_entry_with_override(
+                page_num,
+                npages,
+                prot,
+                PROT_READ | PROT_WRITE,
+                flags,
+                MemoryBackingType::Anonymous,
+                0,
+                0,
+                1,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_commit_charge_tracks_mutations() {
+        let mut vmmap = Vmmap::new();
+        map(&mut vmmap, 0, 10, PROT_READ | PROT_WRITE, 0);
+        map(
+            &mut vmmap,
+            10,
+            10,
+            PROT_READ | PROT_WRITE,
+            MAP_SHARED as i32,
+        );
+        map(
+            &mut vmmap,
+            20,
+            10,
+            PROT_READ | PROT_WRITE,
+            MAP_NORESERVE as i32,
+        );
+        map(&mut vmmap, 30, 10, PROT_READ, 0);
+        assert_eq!(vmmap.commit_charge(), 10);
+
+        vmmap.change_prot(32, 4, PROT_READ | PROT_WRITE).unwrap();
+        vmmap.change_prot(0, 5, PROT_READ).unwrap();
+        assert_eq!(vmmap.commit_charge(), 9);
+
+        // remapping and unmapping give back the replaced charge
+        map(&mut vmmap, 4, 2, PROT_READ | PROT_WRITE, 0);
+        assert_eq!(vmmap.commit_charge(), 10);
+        vmmap.remove_entry(0, 34).unwrap();
+        assert_eq!(vmmap.commit_charge(), 2);
+
+        // entries edited in place were never charged, so unmapping them gives
+        // nothing back
+        let registry = CommitRegistry::shared(OvercommitMode::Always, 0);
+        let mut vmmap = Vmmap::new();
+        vmmap.set_commit_registry(registry.clone());
+        map(&mut vmmap, 0, 4, PROT_READ, 0);
+        vmmap.find_page_mut(0).unwrap().prot = PROT_READ | PROT_WRITE;
+        vmmap.remove_entry(0, 4).unwrap();
+        assert_eq!(vmmap.commit_charge(), 0);
+        assert_eq!(registry.lock().unwrap().committed(), 0);
+    }
+
+    #[test]
+    fn test_overcommit_modes() {
+        let registry = CommitRegistry::shared(OvercommitMode::Never, 16);
+        let mut first = Vmmap::new();
+        let mut second = Vmmap::new();
+        map(&mut first, 0, 10, PROT_READ | PROT_WRITE, 0);
+        first.set_commit_registry(registry.clone());
+        second.set_commit_registry(registry.clone());
+
+        // the limit is shared by every cage of the registry
+        let err = second
+            .add_entry_with_override(
+                0,
+                7,
+                PROT_READ | PROT_WRITE,
+                PROT_READ | PROT_WRITE,
+                0,
+                MemoryBackingType::Anonymous,
+                0,
+                0,
+                2,
+            )
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENOMEM));
+        assert!(second.find_page(0).is_none());
+        map(&mut second, 0, 6, PROT_READ | PROT_WRITE, 0);
+        map(&mut second, 6, 6, PROT_READ, 0);
+        let err = second.change_prot(6, 1, PROT_WRITE).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENOMEM));
+        assert_eq!(second.entries.len(), 2);
+        assert_eq!(registry.lock().unwrap().committed(), 16);
+
+        // heuristic mode only refuses single requests over the limit
+        registry.lock().unwrap().mode = OvercommitMode::Heuristic;
+        second.change_prot(6, 6, PROT_WRITE).unwrap();
+        map(&mut second, 20, 17, PROT_READ, 0);
+        assert!(second.change_prot(20, 17, PROT_WRITE).is_err());
+
+        registry.lock().unwrap().mode = OvercommitMode::Always;
+        map(&mut second, 100, 1000, PROT_READ | PROT_WRITE, 0);
+
+        // dropping a cage gives its charge back
+        drop(second);
+        assert_eq!(registry.lock().unwrap().committed(), 10);
+
+        // new entries and forked children are charged like any other mapping
+        registry.lock().unwrap().mode = OvercommitMode::Never;
+        let mut vmmap_entry = create_default_vmmap_entry();
+        vmmap_entry.page_num = 20;
+        vmmap_entry.prot = PROT_READ | PROT_WRITE;
+        vmmap_entry.maxprot = PROT_READ | PROT_WRITE;
+        let err = first.add_entry(vmmap_entry).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENOMEM));
+        let err = first.fork(3).err().unwrap();
+        assert_eq!(err.raw_os_error(), Some(ENOMEM));
+        assert_eq!(registry.lock().unwrap().committed(), 10);
+        registry.lock().unwrap().commit_limit = 20;
+        let child = first.fork(3).unwrap();
+        assert_eq!(child.commit_charge(), 10);
+        assert_eq!(registry.lock().unwrap().committed(), 20);
+    }
+}
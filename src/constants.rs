@@ -20,13 +20,45 @@ pub const MAP_SHARING_MASK: u32 = 0x03;
 pub const MAP_FIXED: u32 = 0x10; /* Interpret addr exactly.  */
 pub const MAP_ANON: u32 = 0x20; /* Don't use a file.  */
 pub const MAP_ANONYMOUS: u32 = MAP_ANON; /* Linux alias.  */
+pub const MAP_NORESERVE: u32 = 0x4000; /* Don't check for reservations.  */
+pub const MAP_FIXED_NOREPLACE: u32 = 0x100000; /* MAP_FIXED which doesn't unmap underlying mapping.  */
 
 pub const MAP_FAILED: *mut std::ffi::c_void = (-1isize) as *mut std::ffi::c_void;
 
 pub const MREMAP_MAYMOVE: u32 = 0x01;
 pub const MREMAP_FIXED: u32 = 0x02;
 
-pub const O_ACCMODE: i32 = 0003;
-pub const O_RDONLY: i32 = 00;
-pub const O_WRONLY: i32 = 01;
-pub const O_RDWR: i32 = 02;
+pub const O_ACCMODE: i32 = 0o003;
+pub const O_RDONLY: i32 = 0o0;
+pub const O_WRONLY: i32 = 0o1;
+pub const O_RDWR: i32 = 0o2;
+
+pub const EAGAIN: i32 = 11; /* Try again */
+pub const ENOMEM: i32 = 12; /* Out of memory */
+pub const EACCES: i32 = 13; /* Permission denied */
+pub const EFAULT: i32 = 14; /* Bad address */
+pub const EBUSY: i32 = 16; /* Device or resource busy */
+pub const EEXIST: i32 = 17; /* File exists */
+pub const EINVAL: i32 = 22; /* Invalid argument */
+pub const ENAMETOOLONG: i32 = 36; /* File name too long */
+pub const EOVERFLOW: i32 = 75; /* Value too large for defined data type */
+
+pub const MS_ASYNC: i32 = 1; /* Sync memory asynchronously.  */
+pub const MS_INVALIDATE: i32 = 2; /* Invalidate the caches.  */
+pub const MS_SYNC: i32 = 4; /* Synchronous memory sync.  */
+
+pub const MCL_CURRENT: i32 = 1; /* Lock all currently mapped pages.  */
+pub const MCL_FUTURE: i32 = 2; /* Lock all additions to address space.  */
+
+pub const MADV_NORMAL: i32 = 0; /* No further special treatment.  */
+pub const MADV_RANDOM: i32 = 1; /* Expect random page references.  */
+pub const MADV_SEQUENTIAL: i32 = 2; /* Expect sequential page references.  */
+pub const MADV_WILLNEED: i32 = 3; /* Will need these pages.  */
+pub const MADV_DONTNEED: i32 = 4; /* Don't need these pages.  */
+pub const MADV_FREE: i32 = 8; /* Free pages only if memory pressure.  */
+pub const MADV_DONTFORK: i32 = 10; /* Do not inherit across fork.  */
+pub const MADV_DOFORK: i32 = 11; /* Do inherit across fork.  */
+pub const MADV_HUGEPAGE: i32 = 14; /* Worth backing with hugepages.  */
+pub const MADV_NOHUGEPAGE: i32 = 15; /* Not worth backing with hugepages.  */
+pub const MADV_WIPEONFORK: i32 = 18; /* Zero memory on fork, child only.  */
+pub const MADV_KEEPONFORK: i32 = 19; /* Undo MADV_WIPEONFORK.  */
@@ -0,0 +1,314 @@
+use std::collections::BTreeSet;
+
+use nodit::interval::ie;
+use nodit::Interval;
+
+use crate::store::EntryStore;
+use crate::types::{MemoryBackingType, VmmapEntry, VmmapGeometry};
+use crate::vmmap::Vmmap;
+
+/// What a single page is mapped as, the part of an entry `Vmmap::diff` compares
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PageAttributes {
+    pub prot: i32,
+    pub maxprot: i32,
+    pub flags: i32,
+    pub backing: MemoryBackingType,
+    pub file_offset: Option<i64>, // offset of the page in its file, None unless file or shm backed
+}
+
+impl PageAttributes {
+    /// Attributes of `page_num`, which `entry` maps
+    pub(crate) fn of(entry: &VmmapEntry, page_num: u64, geometry: &VmmapGeometry) -> Option<Self> {
+        let file_offset = match entry.backing {
+            MemoryBackingType::FileDescriptor(_) | MemoryBackingType::SharedMemory(_) => {
+                // saturates the same way as the offsets of split entries
+                let skipped = geometry.pages_to_bytes(page_num - entry.page_num)?;
+                Some(
+                    i64::try_from(skipped)
+                        .ok()
+                        .and_then(|skipped| entry.file_offset.checked_add(skipped))
+                        .unwrap_or(i64::MAX),
+                )
+            }
+            MemoryBackingType::Anonymous | MemoryBackingType::None => None,
+        };
+        Some(PageAttributes {
+            prot: entry.prot,
+            maxprot: entry.maxprot,
+            flags: entry.flags,
+            backing: entry.backing,
+            file_offset,
+        })
+    }
+
+    /// Attributes of the page `npages` further into the same mapping, or None if
+    /// its file offset does not fit in an `i64`
+    fn advanced(mut self, npages: u64, geometry: &VmmapGeometry) -> Option<Self> {
+        if let Some(offset) = &mut self.file_offset {
+            *offset = geometry
+                .pages_to_bytes(npages)
+                .and_then(|bytes| i64::try_from(bytes).ok())
+                .and_then(|bytes| offset.checked_add(bytes))?;
+        }
+        Some(self)
+    }
+}
+
+/// Range of pages that differs between two vmmaps. Attributes are the ones of
+/// the first page of the range, later pages only differ by their file offset.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum VmmapDiff {
+    /// Mapped in the other vmmap only
+    Added {
+        interval: Interval<u64>,
+        new: PageAttributes,
+    },
+    /// Mapped in this vmmap only
+    Removed {
+        interval: Interval<u64>,
+        old: PageAttributes,
+    },
+    /// Mapped in both, differently
+    Changed {
+        interval: Interval<u64>,
+        old: PageAttributes,
+        new: PageAttributes,
+    },
+}
+
+impl<S: EntryStore> Vmmap<S> {
+    /// Attributes of `page_num`, or None if it is not mapped
+    pub fn page_attributes(&self, page_num: u64) -> Option<PageAttributes> {
+        let entry = self.entries.get_at_point(page_num)?;
+        PageAttributes::of(entry, page_num, &self.geometry)
+    }
+
+    /// Compares this vmmap with `other` page by page and returns the ranges that
+    /// differ, in address order. How either side is split into entries does not
+    /// matter: consecutive pages with the same difference are reported as one
+    /// range. Both vmmaps are expected to use the same page size.
+    pub fn diff<T: EntryStore>(&self, other: &Vmmap<T>) -> Vec<VmmapDiff> {
+        let boundaries: BTreeSet<u64> = self
+            .entries
+            .iter()
+            .chain(other.entries.iter())
+            .flat_map(|(interval, _)| [interval.start(), interval.end() + 1])
+            .collect();
+        let boundaries: Vec<u64> = boundaries.into_iter().collect();
+
+        let mut diffs: Vec<VmmapDiff> = Vec::new();
+        for window in boundaries.windows(2) {
+            let (start, end) = (window[0], window[1]);
+            let diff = match (self.page_attributes(start), other.page_attributes(start)) {
+                (None, None) => continue,
+                (Some(old), Some(new)) if old == new => continue,
+                (None, Some(new)) => VmmapDiff::Added {
+                    interval: ie(start, end),
+                    new,
+                },
+                (Some(old), None) => VmmapDiff::Removed {
+                    interval: ie(start, end),
+                    old,
+                },
+                (Some(old), Some(new)) => VmmapDiff::Changed {
+                    interval: ie(start, end),
+                    old,
+                    new,
+                },
+            };
+
+            match diffs.last_mut() {
+                Some(last) if continues(last, &diff, &self.geometry) => extend(last, end),
+                _ => diffs.push(diff),
+            }
+        }
+        diffs
+    }
+}
+
+/// Whether `next` picks up right where `last` ends, with the same attributes
+fn continues(last: &VmmapDiff, next: &VmmapDiff, geometry: &VmmapGeometry) -> bool {
+    let continued =
+        |interval: &Interval<u64>, attributes: &PageAttributes, next: &PageAttributes| {
+            attributes.advanced(interval.end() + 1 - interval.start(), geometry) == Some(*next)
+        };
+    match (last, next) {
+        (
+            VmmapDiff::Added { interval, new },
+            VmmapDiff::Added {
+                interval: next_interval,
+                new: next_new,
+            },
+        ) => interval.end() + 1 == next_interval.start() && continued(interval, new, next_new),
+        (
+            VmmapDiff::Removed { interval, old },
+            VmmapDiff::Removed {
+                interval: next_interval,
+                old: next_old,
+            },
+        ) => interval.end() + 1 == next_interval.start() && continued(interval, old, next_old),
+        (
+            VmmapDiff::Changed { interval, old, new },
+            VmmapDiff::Changed {
+                interval: next_interval,
+                old: next_old,
+                new: next_new,
+            },
+        ) => {
+            interval.end() + 1 == next_interval.start()
+                && continued(interval, old, next_old)
+                && continued(interval, new, next_new)
+        }
+        _ => false,
+    }
+}
+
+fn extend(diff: &mut VmmapDiff, end_page: u64) {
+    let (VmmapDiff::Added { interval, .. }
+    | VmmapDiff::Removed { interval, .. }
+    | VmmapDiff::Changed { interval, .. }) = diff;
+    *interval = ie(interval.start(), end_page);
+}
+
+#[cfg(test)]
+mod tests {
+    use nodit::interval::ie;
+
+    use crate::constants::{MAP_SHARED, PAGESIZE, PROT_READ, PROT_WRITE};
+    use crate::types::{MemoryBackingType, VmmapOps};
+    use crate::vmmap::Vmmap;
+
+    use super::VmmapDiff;
+
+    fn map(vmmap: &mut Vmmap, page_num: u64, npages: u64, prot: i32, backing: MemoryBackingType) {
+        let file_offset = match backing {
+            MemoryBackingType::FileDescriptor(_) => (page_num * PAGESIZE as u64) as i64,
+            _ => 0,
+        };
+        vmmap
+            .add_entry_with_override(
+                page_num,
+                npages,
+                prot,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED as i32,
+                backing,
+                file_offset,
+                1 << 30,
+                1,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_diff_ignores_splits() {
+        let mut one = Vmmap::new();
+        let mut other = Vmmap::new();
+        map(&mut one, 0, 10, PROT_READ, MemoryBackingType::Anonymous);
+        map(&mut other, 0, 4, PROT_READ, MemoryBackingType::Anonymous);
+        map(&mut other, 4, 6, PROT_READ, MemoryBackingType::Anonymous);
+
+        // file pages mapped in one go or piece by piece at matching offsets
+        map(
+            &mut one,
+            20,
+            10,
+            PROT_READ,
+            MemoryBackingType::FileDescriptor(3),
+        );
+        map(
+            &mut other,
+            20,
+            3,
+            PROT_READ,
+            MemoryBackingType::FileDescriptor(3),
+        );
+        map(
+            &mut other,
+            23,
+            7,
+            PROT_READ,
+            MemoryBackingType::FileDescriptor(3),
+        );
+        other.change_prot(25, 2, PROT_WRITE).unwrap();
+        other.change_prot(25, 2, PROT_READ).unwrap();
+
+        assert!(one.diff(&other).is_empty());
+        assert!(other.diff(&one).is_empty());
+    }
+
+    #[test]
+    fn test_diff_reports_ranges() {
+        let mut one = Vmmap::new();
+        let mut other = Vmmap::new();
+        map(&mut one, 0, 10, PROT_READ, MemoryBackingType::Anonymous);
+        map(&mut other, 5, 10, PROT_READ, MemoryBackingType::Anonymous);
+        other.change_prot(6, 2, PROT_READ | PROT_WRITE).unwrap();
+        map(
+            &mut one,
+            20,
+            4,
+            PROT_READ,
+            MemoryBackingType::FileDescriptor(3),
+        );
+        map(
+            &mut other,
+            20,
+            4,
+            PROT_READ,
+            MemoryBackingType::FileDescriptor(4),
+        );
+
+        let summary: Vec<_> = one
+            .diff(&other)
+            .iter()
+            .map(|diff| match diff {
+                VmmapDiff::Added { interval, .. } => ("added", *interval),
+                VmmapDiff::Removed { interval, .. } => ("removed", *interval),
+                VmmapDiff::Changed { interval, .. } => ("changed", *interval),
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("removed", ie(0, 5)),
+                ("changed", ie(6, 8)),
+                ("added", ie(10, 15)),
+                ("changed", ie(20, 24)),
+            ]
+        );
+
+        match &one.diff(&other)[1] {
+            VmmapDiff::Changed { old, new, .. } => {
+                assert_eq!(old.prot, PROT_READ);
+                assert_eq!(new.prot, PROT_READ | PROT_WRITE);
+            }
+            diff => panic!("unexpected diff {diff:?}"),
+        }
+
+        // a run ends where file offsets would run past the largest one
+        let mut other = Vmmap::new();
+        let last_page_offset = i64::MAX - PAGESIZE as i64 + 1;
+        for page_num in [0, 2] {
+            other
+                .add_entry_with_override(
+                    page_num,
+                    2,
+                    PROT_READ,
+                    PROT_READ,
+                    MAP_SHARED as i32,
+                    MemoryBackingType::FileDescriptor(3),
+                    last_page_offset,
+                    0,
+                    1,
+                )
+                .unwrap();
+        }
+        assert_eq!(
+            other.page_attributes(1).unwrap().file_offset,
+            Some(i64::MAX)
+        );
+        assert_eq!(Vmmap::new().diff(&other).len(), 2);
+    }
+}
@@ -0,0 +1,68 @@
+use crate::constants::{MAP_PAGESHIFT, PAGESHIFT};
+use crate::types::VmmapGeometry;
+
+impl Default for VmmapGeometry {
+    /// 4 KiB pages mapped at 64 KiB granularity, as in native client
+    fn default() -> Self {
+        VmmapGeometry {
+            page_shift: PAGESHIFT,
+            map_page_shift: MAP_PAGESHIFT,
+        }
+    }
+}
+
+impl VmmapGeometry {
+    pub fn new(page_shift: u32, map_page_shift: u32) -> Self {
+        assert!(page_shift < 32, "page size must fit in 32 bits");
+        assert!(
+            page_shift <= map_page_shift && map_page_shift < 32,
+            "map granularity must be a multiple of the page size"
+        );
+        VmmapGeometry {
+            page_shift,
+            map_page_shift,
+        }
+    }
+
+    pub fn page_size(&self) -> u64 {
+        1 << self.page_shift
+    }
+
+    pub fn map_page_size(&self) -> u64 {
+        1 << self.map_page_shift
+    }
+
+    /// Number of pages in one unit of map granularity
+    pub fn pages_per_map(&self) -> u64 {
+        1 << (self.map_page_shift - self.page_shift)
+    }
+
+    /// Page containing `addr`
+    pub fn addr_to_page(&self, addr: u64) -> u64 {
+        addr >> self.page_shift
+    }
+
+    /// Returns None if the page lies beyond the 64-bit address space
+    pub fn page_to_addr(&self, page_num: u64) -> Option<u64> {
+        page_num.checked_mul(self.page_size())
+    }
+
+    /// Number of pages needed to hold `len` bytes
+    pub fn bytes_to_pages(&self, len: u64) -> u64 {
+        len.div_ceil(self.page_size())
+    }
+
+    /// Returns None if the byte count does not fit in 64 bits
+    pub fn pages_to_bytes(&self, npages: u64) -> Option<u64> {
+        npages.checked_mul(self.page_size())
+    }
+
+    /// Returns None if the rounded page count overflows
+    pub fn round_page_num_up_to_map_multiple(&self, npages: u64) -> Option<u64> {
+        npages.checked_next_multiple_of(self.pages_per_map())
+    }
+
+    pub fn trunc_page_num_down_to_map_multiple(&self, npages: u64) -> u64 {
+        npages & !(self.pages_per_map() - 1)
+    }
+}
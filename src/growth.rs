@@ -0,0 +1,244 @@
+use std::io;
+
+use nodit::interval::ie;
+
+use crate::commit::is_commit_charged;
+use crate::constants::{EAGAIN, EINVAL, ENOMEM};
+use crate::store::EntryStore;
+use crate::types::{MemoryBackingType, VmmapEvent, VmmapOp};
+use crate::vmmap::Vmmap;
+
+impl<S: EntryStore> Vmmap<S> {
+    /// Total number of mapped pages, charged against `address_space_limit`
+    pub fn mapped_pages(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|(interval, _)| interval.end() + 1 - interval.start())
+            .sum()
+    }
+
+    /// Whether mapping every page of `[start_page, end_page)` keeps the vmmap within
+    /// its address space limit. Pages of the range that are already mapped are
+    /// replaced, so they are not charged twice. An empty range is always allowed.
+    pub fn address_space_allows(&self, start_page: u64, end_page: u64) -> bool {
+        let Some(limit) = self.address_space_limit else {
+            return true;
+        };
+        if start_page >= end_page {
+            return true;
+        }
+
+        let already_mapped: u64 = self
+            .entries
+            .overlapping(ie(start_page, end_page))
+            .map(|(interval, _)| {
+                end_page.min(interval.end() + 1) - start_page.max(interval.start())
+            })
+            .sum();
+
+        self.mapped_pages()
+            .checked_sub(already_mapped)
+            .and_then(|pages| pages.checked_add(end_page - start_page))
+            .is_some_and(|pages| pages <= limit)
+    }
+
+    /// Extends the mapping containing `page_num` by `npages` pages past its end, the
+    /// way brk and in place mremap grow a mapping
+    pub fn grow_up(&mut self, page_num: u64, npages: u64) -> Result<(), io::Error> {
+        let range = self
+            .entries
+            .get_key_value_at_point(page_num)
+            .and_then(|(interval, _)| {
+                let start_page = interval.end() + 1;
+                Some((start_page, start_page.checked_add(npages)?))
+            });
+        let result = match range {
+            Some((start_page, end_page)) => self.grow(page_num, start_page, end_page),
+            None => Err(io::Error::from_raw_os_error(ENOMEM)),
+        };
+        self.record(VmmapOp::GrowUp { page_num, npages }, &result);
+        self.debug_validate();
+        result
+    }
+
+    /// Extends the mapping containing `page_num` by `npages` pages below its start,
+    /// the way a fault right under a MAP_GROWSDOWN stack grows it
+    pub fn grow_down(&mut self, page_num: u64, npages: u64) -> Result<(), io::Error> {
+        let range = self
+            .entries
+            .get_key_value_at_point(page_num)
+            .and_then(|(interval, _)| {
+                let end_page = interval.start();
+                Some((end_page.checked_sub(npages)?, end_page))
+            });
+        let result = match range {
+            Some((start_page, end_page)) => self.grow(page_num, start_page, end_page),
+            None => Err(io::Error::from_raw_os_error(ENOMEM)),
+        };
+        self.record(VmmapOp::GrowDown { page_num, npages }, &result);
+        self.debug_validate();
+        result
+    }
+
+    /// Adds `[start_page, end_page)`, which must be free and adjacent to the
+    /// mapping containing `page_num`, to that mapping. Fails with ENOMEM, leaving
+    /// the vmmap untouched, if the pages are taken, outside the vmmap or over the
+    /// address space limit, and with EAGAIN if a locked mapping would exceed the
+    /// memlock limit.
+    fn grow(&mut self, page_num: u64, start_page: u64, end_page: u64) -> Result<(), io::Error> {
+        if start_page == end_page {
+            return Ok(());
+        }
+        if start_page < self.start_page
+            || end_page > self.end_page
+            || self.entries.overlaps(ie(start_page, end_page))
+            || !self.address_space_allows(start_page, end_page)
+        {
+            return Err(io::Error::from_raw_os_error(ENOMEM));
+        }
+
+        let (interval, entry) = self.entries.get_key_value_at_point(page_num).unwrap();
+        let interval = *interval;
+        let mut entry = entry.clone();
+        if entry.locked && !self.memlock_allows(start_page, end_page) {
+            return Err(io::Error::from_raw_os_error(EAGAIN));
+        }
+
+        // pages grown below the original start shift the per page indexing
+        let shift = entry.page_num.saturating_sub(start_page);
+        if shift > 0 {
+            if let MemoryBackingType::FileDescriptor(_) | MemoryBackingType::SharedMemory(_) =
+                entry.backing
+            {
+                let shift_bytes = self
+                    .geometry
+                    .pages_to_bytes(shift)
+                    .and_then(|bytes| i64::try_from(bytes).ok())
+                    .filter(|bytes| *bytes <= entry.file_offset)
+                    .ok_or_else(|| io::Error::from_raw_os_error(EINVAL))?;
+                entry.file_offset -= shift_bytes;
+            }
+            entry.page_num -= shift;
+        }
+        entry.npages = (entry.npages + shift).max(end_page - entry.page_num);
+        if let Some(tracking) = &mut entry.page_tracking {
+            *tracking = tracking.grown(shift, entry.npages);
+        }
+
+        if is_commit_charged(entry.flags, entry.prot) {
+            self.recharge(0, end_page - start_page)?;
+        }
+
+        self.cached_entry = None;
+        if let Some(memory) = &mut self.memory {
+            memory.discard(start_page, end_page);
+        }
+        let grown_interval = ie(
+            start_page.min(interval.start()),
+            end_page.max(interval.end() + 1),
+        );
+        let event = self.observed().then(|| VmmapEvent::Mapped {
+            interval: ie(start_page, end_page),
+            entry: entry.clone(),
+        });
+        self.entries.remove_overlapping(grown_interval);
+        let _ = self.entries.insert_strict(grown_interval, entry);
+
+        if let Some(event) = event {
+            self.notify(vec![event]);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{EAGAIN, ENOMEM, PROT_READ, PROT_WRITE};
+    use crate::types::{MemoryBackingType, PageState, VmmapOps};
+    use crate::vmmap::Vmmap;
+    use crate::vmmap_entries::test_vmmap_entry_util::create_default_vmmap_entry;
+
+    fn map(vmmap: &mut Vmmap, page_num: u64, npages: u64) -> Result<(), std::io::Error> {
+        vmmap.add_entry_with_override(
+            page_num,
+            npages,
+            PROT_READ | PROT_WRITE,
+            PROT_READ | PROT_WRITE,
+            0,
+            MemoryBackingType::Anonymous,
+            0,
+            0,
+            1,
+        )
+    }
+
+    #[test]
+    fn test_address_space_limit() {
+        let mut vmmap = Vmmap::new();
+        vmmap.address_space_limit = Some(16);
+        map(&mut vmmap, 0, 10).unwrap();
+
+        let err = map(&mut vmmap, 20, 7).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENOMEM));
+        assert!(vmmap.find_page(20).is_none());
+
+        // remapping over existing pages only charges the new ones
+        map(&mut vmmap, 5, 11).unwrap();
+        assert_eq!(vmmap.mapped_pages(), 16);
+        assert!(vmmap.address_space_allows(5, 5));
+        assert!(!vmmap.address_space_allows(20, u64::MAX));
+
+        // unmapping is always allowed and frees up room
+        vmmap.remove_entry(0, 4).unwrap();
+        map(&mut vmmap, 20, 4).unwrap();
+        assert_eq!(vmmap.mapped_pages(), 16);
+
+        // entries added directly are held to the same limit
+        let mut vmmap_entry = create_default_vmmap_entry();
+        vmmap_entry.page_num = 30;
+        let err = vmmap.add_entry(vmmap_entry).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENOMEM));
+        assert!(vmmap.find_page(30).is_none());
+    }
+
+    #[test]
+    fn test_grow_up_and_down() {
+        let mut vmmap = Vmmap::new();
+        vmmap.address_space_limit = Some(20);
+        map(&mut vmmap, 10, 4).unwrap();
+        map(&mut vmmap, 30, 4).unwrap();
+        vmmap.mark_pages(10, 1, PageState::Dirty).unwrap();
+
+        // brk style growth keeps the recorded page state
+        vmmap.grow_up(12, 6).unwrap();
+        assert_eq!(vmmap.find_page(19).unwrap().npages, 10);
+        vmmap.mark_pages(19, 1, PageState::Dirty).unwrap();
+        assert_eq!(vmmap.pages_in_state(0, 40, PageState::Dirty), [10, 19]);
+
+        // growing into another mapping fails
+        let err = vmmap.grow_down(31, 11).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENOMEM));
+
+        // stack style growth, up to the limit
+        vmmap.grow_down(31, 4).unwrap();
+        assert_eq!(vmmap.find_page(26).unwrap().page_num, 26);
+        assert_eq!(vmmap.mapped_pages(), 18);
+        let err = vmmap.grow_down(26, 3).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENOMEM));
+        assert!(vmmap.find_page(25).is_none());
+        assert_eq!(vmmap.pages_in_state(0, 40, PageState::Dirty), [10, 19]);
+
+        // growing locked mappings is charged against the memlock limit as well
+        vmmap.address_space_limit = None;
+        vmmap.memlock_limit = Some(9);
+        vmmap.mlock(26, 8).unwrap();
+        let err = vmmap.grow_up(26, 2).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(EAGAIN));
+        vmmap.grow_up(26, 1).unwrap();
+        assert_eq!(vmmap.locked_pages(), 9);
+
+        let err = vmmap.grow_up(40, 1).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENOMEM));
+    }
+}
@@ -0,0 +1,532 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+use crate::store::EntryStore;
+use crate::types::{MemoryBackingType, VmmapOp};
+use crate::vmmap::Vmmap;
+
+/// First line of every journal, bumped whenever the format changes
+pub const JOURNAL_HEADER: &str = "rust_vmmap journal v1";
+
+/// One journaled call along with what it returned
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct JournalRecord {
+    pub op: VmmapOp,
+    pub result: Result<(), Option<i32>>, // errno of the failure, if it had one
+    pub digest: u64,                     // `Vmmap::layout_digest` right after the call
+}
+
+/// Append-only record of the mutating calls made on a vmmap, which `replay`
+/// applies to another vmmap to reproduce the same state.
+///
+/// The format is line based text. The first line is `JOURNAL_HEADER`, every
+/// following line is one call: the operation name, its arguments in the order
+/// of the matching `VmmapOp` fields, `->`, the outcome, which is `ok`,
+/// `errno <n>` or `error` for failures without an errno, then `@` and the
+/// layout digest of the vmmap after the call in hex. Backings are written as
+/// `none`, `anon`, `shm:<shmid>` or `fd:<fd>`. For example:
+///
+/// ```text
+/// rust_vmmap journal v1
+/// map 16 4 3 7 2 anon 0 0 1 -> ok @ 7174b7f9b67213e0
+/// protect 16 1 1 -> ok @ a372c612c24dace5
+/// mlock 40 1 -> errno 12 @ a372c612c24dace5
+/// ```
+///
+/// Page state marking and simulated memory writes are not journaled, they do
+/// not change the layout of the vmmap.
+pub struct Journal {
+    sink: Box<dyn Write + Send>,
+    error: Option<io::Error>, // first write failure, reported by `stop_journal`
+}
+
+impl Journal {
+    fn append(&mut self, record: &JournalRecord) {
+        if self.error.is_none() {
+            if let Err(err) = writeln!(self.sink, "{record}") {
+                self.error = Some(err);
+            }
+        }
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl fmt::Display for JournalRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.op {
+            VmmapOp::Map {
+                page_num,
+                npages,
+                prot,
+                maxprot,
+                flags,
+                backing,
+                file_offset,
+                file_size,
+                cage_id,
+            }
+            | VmmapOp::Insert {
+                page_num,
+                npages,
+                prot,
+                maxprot,
+                flags,
+                backing,
+                file_offset,
+                file_size,
+                cage_id,
+            } => {
+                let name = match self.op {
+                    VmmapOp::Insert { .. } => "insert",
+                    _ => "map",
+                };
+                let backing = match backing {
+                    MemoryBackingType::None => "none".to_string(),
+                    MemoryBackingType::Anonymous => "anon".to_string(),
+                    MemoryBackingType::SharedMemory(shmid) => format!("shm:{shmid}"),
+                    MemoryBackingType::FileDescriptor(fd) => format!("fd:{fd}"),
+                };
+                write!(
+                    f,
+                    "{name} {page_num} {npages} {prot} {maxprot} {flags} {backing} {file_offset} {file_size} {cage_id}"
+                )?
+            }
+            VmmapOp::Unmap { page_num, npages } => write!(f, "unmap {page_num} {npages}")?,
+            VmmapOp::Protect {
+                page_num,
+                npages,
+                prot,
+            } => write!(f, "protect {page_num} {npages} {prot}")?,
+            VmmapOp::Mlock { page_num, npages } => write!(f, "mlock {page_num} {npages}")?,
+            VmmapOp::Munlock { page_num, npages } => write!(f, "munlock {page_num} {npages}")?,
+            VmmapOp::MlockAll { flags } => write!(f, "mlockall {flags}")?,
+            VmmapOp::MunlockAll => write!(f, "munlockall")?,
+            VmmapOp::Madvise { addr, len, advice } => write!(f, "madvise {addr} {len} {advice}")?,
+            VmmapOp::GrowUp { page_num, npages } => write!(f, "grow_up {page_num} {npages}")?,
+            VmmapOp::GrowDown { page_num, npages } => write!(f, "grow_down {page_num} {npages}")?,
+        }
+        match self.result {
+            Ok(()) => write!(f, " -> ok")?,
+            Err(Some(errno)) => write!(f, " -> errno {errno}")?,
+            Err(None) => write!(f, " -> error")?,
+        }
+        write!(f, " @ {:016x}", self.digest)
+    }
+}
+
+fn malformed(line: &str) -> io::Error {
+    invalid_data(format!("Malformed journal record `{line}`"))
+}
+
+/// Parses the argument at `index` of the record `line`
+fn arg<T: FromStr>(args: &[&str], index: usize, line: &str) -> Result<T, io::Error> {
+    args[index].parse().map_err(|_| malformed(line))
+}
+
+impl FromStr for JournalRecord {
+    type Err = io::Error;
+
+    fn from_str(line: &str) -> Result<Self, io::Error> {
+        let (call, outcome) = line.split_once(" -> ").ok_or_else(|| malformed(line))?;
+        let (outcome, digest) = outcome.split_once(" @ ").ok_or_else(|| malformed(line))?;
+        let digest = u64::from_str_radix(digest.trim(), 16).map_err(|_| malformed(line))?;
+        let mut words = call.split_whitespace();
+        let name = words.next().ok_or_else(|| malformed(line))?;
+        let args: Vec<&str> = words.collect();
+
+        let op = match (name, args.len()) {
+            ("map" | "insert", 9) => {
+                let backing = match args[5].split_once(':') {
+                    None if args[5] == "none" => MemoryBackingType::None,
+                    None if args[5] == "anon" => MemoryBackingType::Anonymous,
+                    Some(("shm", shmid)) => {
+                        MemoryBackingType::SharedMemory(shmid.parse().map_err(|_| malformed(line))?)
+                    }
+                    Some(("fd", fd)) => {
+                        MemoryBackingType::FileDescriptor(fd.parse().map_err(|_| malformed(line))?)
+                    }
+                    _ => return Err(malformed(line)),
+                };
+                let (page_num, npages) = (arg(&args, 0, line)?, arg(&args, 1, line)?);
+                let (prot, maxprot, flags) = (
+                    arg(&args, 2, line)?,
+                    arg(&args, 3, line)?,
+                    arg(&args, 4, line)?,
+                );
+                let (file_offset, file_size, cage_id) = (
+                    arg(&args, 6, line)?,
+                    arg(&args, 7, line)?,
+                    arg(&args, 8, line)?,
+                );
+                if name == "insert" {
+                    VmmapOp::Insert {
+                        page_num,
+                        npages,
+                        prot,
+                        maxprot,
+                        flags,
+                        backing,
+                        file_offset,
+                        file_size,
+                        cage_id,
+                    }
+                } else {
+                    VmmapOp::Map {
+                        page_num,
+                        npages,
+                        prot,
+                        maxprot,
+                        flags,
+                        backing,
+                        file_offset,
+                        file_size,
+                        cage_id,
+                    }
+                }
+            }
+            ("unmap", 2) => VmmapOp::Unmap {
+                page_num: arg(&args, 0, line)?,
+                npages: arg(&args, 1, line)?,
+            },
+            ("protect", 3) => VmmapOp::Protect {
+                page_num: arg(&args, 0, line)?,
+                npages: arg(&args, 1, line)?,
+                prot: arg(&args, 2, line)?,
+            },
+            ("mlock", 2) => VmmapOp::Mlock {
+                page_num: arg(&args, 0, line)?,
+                npages: arg(&args, 1, line)?,
+            },
+            ("munlock", 2) => VmmapOp::Munlock {
+                page_num: arg(&args, 0, line)?,
+                npages: arg(&args, 1, line)?,
+            },
+            ("mlockall", 1) => VmmapOp::MlockAll {
+                flags: arg(&args, 0, line)?,
+            },
+            ("munlockall", 0) => VmmapOp::MunlockAll,
+            ("madvise", 3) => VmmapOp::Madvise {
+                addr: arg(&args, 0, line)?,
+                len: arg(&args, 1, line)?,
+                advice: arg(&args, 2, line)?,
+            },
+            ("grow_up", 2) => VmmapOp::GrowUp {
+                page_num: arg(&args, 0, line)?,
+                npages: arg(&args, 1, line)?,
+            },
+            ("grow_down", 2) => VmmapOp::GrowDown {
+                page_num: arg(&args, 0, line)?,
+                npages: arg(&args, 1, line)?,
+            },
+            _ => return Err(malformed(line)),
+        };
+
+        let result = match outcome.split_whitespace().collect::<Vec<_>>()[..] {
+            ["ok"] => Ok(()),
+            ["errno", errno] => Err(Some(errno.parse().map_err(|_| malformed(line))?)),
+            ["error"] => Err(None),
+            _ => return Err(malformed(line)),
+        };
+
+        Ok(JournalRecord { op, result, digest })
+    }
+}
+
+/// Reads back a journal written by `Vmmap::start_journal`
+pub fn read_journal(reader: impl BufRead) -> Result<Vec<JournalRecord>, io::Error> {
+    let mut lines = reader.lines();
+    match lines.next().transpose()? {
+        Some(header) if header == JOURNAL_HEADER => {}
+        _ => return Err(invalid_data("Missing journal header".to_string())),
+    }
+
+    lines
+        .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+        .map(|line| line?.parse())
+        .collect()
+}
+
+/// Applies `records` in order to `vmmap`, which should be configured the way the
+/// journaled vmmap was when recording started. Fails with `InvalidData` as soon
+/// as a call does not return what it returned when it was recorded, or leaves
+/// the vmmap with a different layout digest, so a successful replay ends in the
+/// same state the journaled vmmap was in.
+pub fn replay<S: EntryStore>(
+    vmmap: &mut Vmmap<S>,
+    records: &[JournalRecord],
+) -> Result<(), io::Error> {
+    for (index, record) in records.iter().enumerate() {
+        let result = vmmap.apply_op(&record.op).map_err(|err| err.raw_os_error());
+        if result != record.result {
+            return Err(invalid_data(format!(
+                "Record {index} `{record}` diverged, replay returned {result:?}"
+            )));
+        }
+        let digest = vmmap.layout_digest();
+        if digest != record.digest {
+            return Err(invalid_data(format!(
+                "Record {index} `{record}` diverged, replay left digest {digest:016x}"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Folds `value` into an FNV-1a hash
+fn fnv1a(hash: u64, value: u64) -> u64 {
+    value.to_le_bytes().iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl<S: EntryStore> Vmmap<S> {
+    /// Hash of everything the journaled calls can change: the interval, protection,
+    /// flags, backing, file offset and size, owner, lock and advice of every entry,
+    /// and whether future mappings get locked. Page tracking is left out.
+    pub fn layout_digest(&self) -> u64 {
+        let mut hash = 0xcbf29ce484222325;
+        for (interval, entry) in self.entries.iter() {
+            let (backing, id) = match entry.backing {
+                MemoryBackingType::None => (0, 0),
+                MemoryBackingType::Anonymous => (1, 0),
+                MemoryBackingType::SharedMemory(shmid) => (2, shmid),
+                MemoryBackingType::FileDescriptor(fd) => (3, fd),
+            };
+            let advice = &entry.advice;
+            for value in [
+                interval.start(),
+                interval.end(),
+                entry.prot as u64,
+                entry.maxprot as u64,
+                entry.flags as u64,
+                backing,
+                id,
+                entry.file_offset as u64,
+                entry.file_size as u64,
+                entry.cage_id,
+                entry.locked as u64,
+                advice.access_pattern as u64,
+                advice.dont_fork as u64
+                    | (advice.wipe_on_fork as u64) << 1
+                    | (advice.hugepage as u64) << 2,
+            ] {
+                hash = fnv1a(hash, value);
+            }
+        }
+        fnv1a(hash, self.lock_future as u64)
+    }
+
+    /// Starts appending every mutating call to `sink`, beginning with the header
+    pub fn start_journal(
+        &mut self,
+        mut sink: impl Write + Send + 'static,
+    ) -> Result<(), io::Error> {
+        writeln!(sink, "{JOURNAL_HEADER}")?;
+        self.journal = Some(Journal {
+            sink: Box::new(sink),
+            error: None,
+        });
+        Ok(())
+    }
+
+    /// Stops journaling and flushes the sink. Reports the first write that failed
+    /// while recording, as the calls themselves cannot fail because of it.
+    pub fn stop_journal(&mut self) -> Result<(), io::Error> {
+        let Some(mut journal) = self.journal.take() else {
+            return Ok(());
+        };
+        if let Some(err) = journal.error {
+            return Err(err);
+        }
+        journal.sink.flush()
+    }
+
+    /// Appends a call and its result to the journal, if one is being kept
+    pub(crate) fn record(&mut self, op: VmmapOp, result: &Result<(), io::Error>) {
+        if self.journal.is_some() {
+            let digest = self.layout_digest();
+            self.record_with_digest(op, result, digest);
+        }
+    }
+
+    /// Like `record`, for an op whose resulting layout was digested earlier,
+    /// e.g. one of several applied together by a transaction
+    pub(crate) fn record_with_digest(
+        &mut self,
+        op: VmmapOp,
+        result: &Result<(), io::Error>,
+        digest: u64,
+    ) {
+        if let Some(journal) = &mut self.journal {
+            journal.append(&JournalRecord {
+                op,
+                result: result
+                    .as_ref()
+                    .map(|_| ())
+                    .map_err(|err| err.raw_os_error()),
+                digest,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    use crate::constants::{
+        MADV_DONTFORK, MAP_SHARED, MCL_FUTURE, PAGESIZE, PROT_READ, PROT_WRITE,
+    };
+    use crate::types::{MemoryBackingType, VmmapOp, VmmapOps};
+    use crate::vmmap::Vmmap;
+    use crate::vmmap_entries::test_vmmap_entry_util::create_default_vmmap_entry;
+
+    use super::{read_journal, replay, JournalRecord};
+
+    /// Sink the test can read back after handing it to the vmmap
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn record_session(vmmap: &mut Vmmap) {
+        let map = |vmmap: &mut Vmmap, page_num, npages, flags, backing| {
+            vmmap.add_entry_with_override(
+                page_num,
+                npages,
+                PROT_READ | PROT_WRITE,
+                PROT_READ | PROT_WRITE,
+                flags,
+                backing,
+                0,
+                1 << 20,
+                1,
+            )
+        };
+        map(vmmap, 0, 10, 0, MemoryBackingType::Anonymous).unwrap();
+        map(
+            vmmap,
+            16,
+            4,
+            MAP_SHARED as i32,
+            MemoryBackingType::FileDescriptor(7),
+        )
+        .unwrap();
+        assert!(map(vmmap, 30, 0, 0, MemoryBackingType::Anonymous).is_err());
+        vmmap.change_prot(2, 3, PROT_READ).unwrap();
+        vmmap.mlock(6, 2).unwrap();
+        assert!(vmmap.mlock(8, 4).is_err());
+        vmmap
+            .madvise(PAGESIZE as u64, PAGESIZE as u64, MADV_DONTFORK)
+            .unwrap();
+        vmmap.mlockall(MCL_FUTURE).unwrap();
+        vmmap.grow_up(17, 2).unwrap();
+        vmmap.munlockall();
+        vmmap.remove_entry(3, 5).unwrap();
+
+        // added entries never replace existing ones, so they are journaled apart
+        let mut vmmap_entry = create_default_vmmap_entry();
+        vmmap_entry.page_num = 40;
+        vmmap_entry.npages = 4;
+        vmmap.add_entry(vmmap_entry.clone()).unwrap();
+        assert!(vmmap.add_entry(vmmap_entry).is_err());
+
+        // each op of a transaction is journaled with the layout it left behind
+        let mut transaction = vmmap.transaction();
+        transaction
+            .stage(VmmapOp::Unmap {
+                page_num: 0,
+                npages: 1,
+            })
+            .stage(VmmapOp::Protect {
+                page_num: 1,
+                npages: 1,
+                prot: PROT_READ,
+            });
+        transaction.commit().unwrap();
+    }
+
+    #[test]
+    fn test_journal_replay_reproduces_state() {
+        let mut vmmap = Vmmap::new();
+        let buffer = SharedBuffer::default();
+        vmmap.start_journal(buffer.clone()).unwrap();
+        record_session(&mut vmmap);
+        vmmap.stop_journal().unwrap();
+
+        let bytes = buffer.0.lock().unwrap().clone();
+        let records = read_journal(bytes.as_slice()).unwrap();
+        assert_eq!(records.len(), 15);
+        assert!(records[2]
+            .to_string()
+            .starts_with("map 30 0 3 3 0 anon 0 1048576 1 -> error @ "));
+        assert!(records[5]
+            .to_string()
+            .starts_with("mlock 8 4 -> errno 12 @ "));
+        assert!(records[12]
+            .to_string()
+            .starts_with("insert 40 4 0 0 0 anon 0 0 1 -> errno 17 @ "));
+        assert_ne!(records[13].digest, records[14].digest);
+        assert_eq!(records[14].digest, vmmap.layout_digest());
+
+        let mut replayed = Vmmap::new();
+        replay(&mut replayed, &records).unwrap();
+        assert_eq!(&replayed.entries, &vmmap.entries);
+        assert_eq!(replayed.commit_charge(), vmmap.commit_charge());
+    }
+
+    #[test]
+    fn test_journal_format_errors() {
+        let records = [
+            "map 0 10 3 3 0 fd:4 4096 8192 2 -> ok @ 00000000deadbeef",
+            "insert 0 10 3 3 0 anon 0 0 2 -> errno 17 @ 00000000deadbeef",
+            "munlockall -> ok @ 0123456789abcdef",
+        ];
+        for line in records {
+            assert_eq!(line.parse::<JournalRecord>().unwrap().to_string(), line);
+        }
+
+        assert!("map 0 10 3 3 0 pipe 0 0 1 -> ok @ 0"
+            .parse::<JournalRecord>()
+            .is_err());
+        assert!("unmap 0 -> ok @ 0".parse::<JournalRecord>().is_err());
+        assert!("unmap 0 1 -> ok".parse::<JournalRecord>().is_err());
+        assert!("unmap 0 1 -> ok @ xyz".parse::<JournalRecord>().is_err());
+        assert!(read_journal("unmap 0 1 -> ok @ 0\n".as_bytes()).is_err());
+
+        // a replay that does not return the recorded result is reported
+        let digest = Vmmap::new().layout_digest();
+        let journal = format!(
+            "{}\nunmap 0 1 -> errno 12 @ {digest:016x}\n",
+            super::JOURNAL_HEADER
+        );
+        let records = read_journal(journal.as_bytes()).unwrap();
+        let err = replay(&mut Vmmap::new(), &records).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // and so is one that returns it but ends up in another state
+        let journal = format!(
+            "{}\nunmap 0 1 -> ok @ {digest:016x}\n",
+            super::JOURNAL_HEADER
+        );
+        let records = read_journal(journal.as_bytes()).unwrap();
+        replay(&mut Vmmap::new(), &records).unwrap();
+        let mut vmmap = Vmmap::new();
+        vmmap.mlockall(MCL_FUTURE).unwrap();
+        let err = replay(&mut vmmap, &records).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,5 +1,25 @@
+pub mod commit;
 #[allow(dead_code)]
 mod constants;
+pub mod diff;
+pub mod geometry;
+pub mod growth;
+pub mod journal;
+pub mod madvise;
+pub mod memory;
+pub mod mlock;
+pub mod msync;
+pub mod observer;
+pub mod page_state;
+pub mod placement;
+#[cfg(feature = "reference")]
+pub mod reference;
+pub mod store;
+pub mod strace;
+pub mod transaction;
 pub mod types;
+pub mod usercopy;
+pub mod utils;
+pub mod validate;
 pub mod vmmap;
 pub mod vmmap_entries;
@@ -0,0 +1,261 @@
+use std::io;
+
+use nodit::interval::ie;
+
+use crate::constants::{
+    EINVAL, ENOMEM, MADV_DOFORK, MADV_DONTFORK, MADV_DONTNEED, MADV_FREE, MADV_HUGEPAGE,
+    MADV_KEEPONFORK, MADV_NOHUGEPAGE, MADV_NORMAL, MADV_RANDOM, MADV_SEQUENTIAL, MADV_WILLNEED,
+    MADV_WIPEONFORK, MAP_SHARED,
+};
+use crate::store::EntryStore;
+use crate::types::{Advice, MemoryBackingType, PageState, VmmapEntry, VmmapOp};
+use crate::vmmap::Vmmap;
+
+/// MADV_WIPEONFORK and MADV_FREE only apply to private anonymous memory
+fn is_private_anonymous(entry: &VmmapEntry) -> bool {
+    entry.backing == MemoryBackingType::Anonymous && entry.flags & MAP_SHARED as i32 == 0
+}
+
+/// Records a persistent piece of advice
+fn apply_advice(recorded: &mut Advice, advice: i32) {
+    match advice {
+        MADV_NORMAL | MADV_RANDOM | MADV_SEQUENTIAL => recorded.access_pattern = advice,
+        MADV_DONTFORK => recorded.dont_fork = true,
+        MADV_DOFORK => recorded.dont_fork = false,
+        MADV_WIPEONFORK => recorded.wipe_on_fork = true,
+        MADV_KEEPONFORK => recorded.wipe_on_fork = false,
+        MADV_HUGEPAGE => recorded.hugepage = true,
+        MADV_NOHUGEPAGE => recorded.hugepage = false,
+        _ => {}
+    }
+}
+
+impl<S: EntryStore> Vmmap<S> {
+    /// Registers the callback notified when MADV_DONTNEED or MADV_FREE drop the
+    /// contents of a range, so the runtime can zero the real backing memory
+    pub fn set_discard_callback(&mut self, callback: impl FnMut(u64, u64, i32) + Send + 'static) {
+        self.discard_callback = Some(Box::new(callback));
+    }
+
+    /// Applies `advice` to `[addr, addr + len)`, rounding `len` up to whole pages.
+    ///
+    /// Persistent advice is recorded on the entries, splitting them at the range
+    /// boundaries. MADV_DONTNEED and MADV_FREE clear the dirty/accessed state and
+    /// the simulated memory of the range, and notify the discard callback.
+    ///
+    /// Errors are the ones of the syscall: EINVAL for an unaligned address, unknown
+    /// advice, or advice that does not apply to the mappings in the range, and
+    /// ENOMEM if part of the range is unmapped. The vmmap is left untouched on error.
+    pub fn madvise(&mut self, addr: u64, len: u64, advice: i32) -> Result<(), io::Error> {
+        let result = self.apply_madvise(addr, len, advice);
+        self.record(VmmapOp::Madvise { addr, len, advice }, &result);
+        self.debug_validate();
+        result
+    }
+
+    fn apply_madvise(&mut self, addr: u64, len: u64, advice: i32) -> Result<(), io::Error> {
+        let known = matches!(
+            advice,
+            MADV_NORMAL
+                | MADV_RANDOM
+                | MADV_SEQUENTIAL
+                | MADV_WILLNEED
+                | MADV_DONTNEED
+                | MADV_FREE
+                | MADV_DONTFORK
+                | MADV_DOFORK
+                | MADV_HUGEPAGE
+                | MADV_NOHUGEPAGE
+                | MADV_WIPEONFORK
+                | MADV_KEEPONFORK
+        );
+        if !known || !addr.is_multiple_of(self.geometry.page_size()) {
+            return Err(io::Error::from_raw_os_error(EINVAL));
+        }
+        if len == 0 {
+            return Ok(());
+        }
+
+        let page_num = self.geometry.addr_to_page(addr);
+        let npages = self.geometry.bytes_to_pages(len);
+        let end_page = page_num
+            .checked_add(npages)
+            .ok_or_else(|| io::Error::from_raw_os_error(EINVAL))?;
+        if !self.entries.contains_interval(ie(page_num, end_page)) {
+            return Err(io::Error::from_raw_os_error(ENOMEM));
+        }
+
+        let applicable = |entry: &VmmapEntry| match advice {
+            MADV_DONTNEED => !entry.locked,
+            MADV_FREE => !entry.locked && is_private_anonymous(entry),
+            MADV_WIPEONFORK => is_private_anonymous(entry),
+            _ => true,
+        };
+        if !self
+            .entries
+            .overlapping(ie(page_num, end_page))
+            .all(|(_, entry)| applicable(entry))
+        {
+            return Err(io::Error::from_raw_os_error(EINVAL));
+        }
+
+        match advice {
+            MADV_WILLNEED => {}
+            MADV_DONTNEED | MADV_FREE => {
+                if let Some(memory) = &mut self.memory {
+                    memory.discard(page_num, end_page);
+                }
+                self.clear_pages(page_num, npages, PageState::Dirty);
+                self.clear_pages(page_num, npages, PageState::Accessed);
+                if let Some(callback) = &mut self.discard_callback {
+                    callback(page_num, npages, advice);
+                }
+            }
+            _ => {
+                self.split_at(page_num);
+                self.split_at(end_page);
+                for (_, entry) in self.entries.overlapping_mut(ie(page_num, end_page)) {
+                    apply_advice(&mut entry.advice, advice);
+                }
+                // pieces whose advice is reset join their neighbours again
+                self.merge_around(page_num, end_page);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::constants::{
+        EINVAL, ENOMEM, MADV_DOFORK, MADV_DONTFORK, MADV_DONTNEED, MADV_FREE, MADV_NORMAL,
+        MADV_SEQUENTIAL, MADV_WIPEONFORK, MAP_SHARED, PAGESIZE, PROT_READ, PROT_WRITE,
+    };
+    use crate::types::{MemoryBackingType, PageState, VmmapOps};
+    use crate::vmmap::Vmmap;
+
+    const PAGE: u64 = PAGESIZE as u64;
+
+    fn map(vmmap: &mut Vmmap, page_num: u64, npages: u64, flags: i32) {
+        vmmap
+            .add_entry_with_override(
+                page_num,
+                npages,
+                PROT_READ | PROT_WRITE,
+                PROT_READ | PROT_WRITE,
+                flags,
+                MemoryBackingType::Anonymous,
+                0,
+                0,
+                1,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_madvise_records_advice() {
+        let mut vmmap = Vmmap::new();
+        map(&mut vmmap, 0, 10, 0);
+        map(&mut vmmap, 20, 10, MAP_SHARED as i32);
+
+        vmmap
+            .madvise(2 * PAGE, 3 * PAGE - 1, MADV_SEQUENTIAL)
+            .unwrap();
+        assert_eq!(vmmap.entries.len(), 4);
+        assert_eq!(
+            vmmap.find_page(4).unwrap().advice.access_pattern,
+            MADV_SEQUENTIAL
+        );
+        assert_eq!(vmmap.find_page(5).unwrap().advice.access_pattern, 0);
+
+        // resetting the advice joins the pieces again
+        vmmap.madvise(2 * PAGE, 3 * PAGE, MADV_NORMAL).unwrap();
+        assert_eq!(vmmap.entries.len(), 2);
+        vmmap.madvise(2 * PAGE, 3 * PAGE, MADV_DONTFORK).unwrap();
+        assert_eq!(vmmap.entries.len(), 4);
+        vmmap.madvise(2 * PAGE, 3 * PAGE, MADV_DOFORK).unwrap();
+        assert_eq!(vmmap.entries.len(), 2);
+
+        let errno = |result: Result<(), std::io::Error>| result.unwrap_err().raw_os_error();
+        assert_eq!(errno(vmmap.madvise(1, PAGE, MADV_SEQUENTIAL)), Some(EINVAL));
+        assert_eq!(errno(vmmap.madvise(0, PAGE, 1234)), Some(EINVAL));
+        assert_eq!(
+            errno(vmmap.madvise(8 * PAGE, 4 * PAGE, MADV_DONTFORK)),
+            Some(ENOMEM)
+        );
+        assert!(!vmmap.find_page(8).unwrap().advice.dont_fork);
+        // shared mappings cannot be wiped on fork
+        assert_eq!(
+            errno(vmmap.madvise(20 * PAGE, PAGE, MADV_WIPEONFORK)),
+            Some(EINVAL)
+        );
+        assert_eq!(
+            errno(vmmap.madvise(20 * PAGE, PAGE, MADV_FREE)),
+            Some(EINVAL)
+        );
+    }
+
+    #[test]
+    fn test_madvise_dontneed_notifies_callback() {
+        let mut vmmap = Vmmap::new();
+        vmmap.enable_simulated_memory();
+        map(&mut vmmap, 0, 10, 0);
+
+        let discarded = Arc::new(Mutex::new(Vec::new()));
+        let discarded_clone = discarded.clone();
+        vmmap.set_discard_callback(move |page_num, npages, advice| {
+            discarded_clone
+                .lock()
+                .unwrap()
+                .push((page_num, npages, advice))
+        });
+
+        vmmap.write_memory(2 * PAGE, &[1; 16]).unwrap();
+        vmmap.mark_pages(2, 1, PageState::Dirty).unwrap();
+        vmmap.madvise(2 * PAGE, 2 * PAGE, MADV_DONTNEED).unwrap();
+
+        assert_eq!(*discarded.lock().unwrap(), [(2, 2, MADV_DONTNEED)]);
+        assert!(vmmap.pages_in_state(0, 10, PageState::Dirty).is_empty());
+        let mut buf = [1; 16];
+        vmmap.read_memory(2 * PAGE, &mut buf).unwrap();
+        assert_eq!(buf, [0; 16]);
+
+        // locked pages cannot be dropped
+        vmmap.mlock(5, 1).unwrap();
+        let err = vmmap
+            .madvise(4 * PAGE, 2 * PAGE, MADV_DONTNEED)
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(EINVAL));
+        assert_eq!(discarded.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_fork_honors_advice() {
+        let mut vmmap = Vmmap::new();
+        vmmap.enable_simulated_memory();
+        map(&mut vmmap, 0, 3, 0);
+        vmmap.write_memory(0, &[9; 3 * PAGESIZE as usize]).unwrap();
+        vmmap.madvise(PAGE, PAGE, MADV_DONTFORK).unwrap();
+        vmmap.madvise(2 * PAGE, PAGE, MADV_WIPEONFORK).unwrap();
+        vmmap.mlock(0, 1).unwrap();
+
+        let mut child = vmmap.fork(2).unwrap();
+        assert_eq!(child.find_page(0).unwrap().cage_id, 2);
+        assert!(!child.find_page(0).unwrap().locked);
+        assert!(child.find_page(1).is_none());
+        assert!(child.find_page(2).is_some());
+
+        let mut buf = [0; 4];
+        child.read_memory(0, &mut buf).unwrap();
+        assert_eq!(buf, [9; 4]);
+        child.read_memory(2 * PAGE, &mut buf).unwrap();
+        assert_eq!(buf, [0; 4]);
+
+        // the parent keeps its contents
+        vmmap.read_memory(2 * PAGE, &mut buf).unwrap();
+        assert_eq!(buf, [9; 4]);
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use nodit::interval::ii;
+
+use crate::store::EntryStore;
+use crate::types::{AccessKind, MemoryBackingType};
+use crate::vmmap::Vmmap;
+
+/// Software stand-in for the memory of a cage, used to exercise syscall logic
+/// without a real sandbox. Only anonymous mappings are simulated: their pages
+/// read as zero until first written, at which point byte storage is allocated.
+#[derive(Clone, Default)]
+pub struct SimulatedMemory {
+    pages: BTreeMap<u64, Box<[u8]>>, // Keyed by page number, only holds written pages
+}
+
+impl SimulatedMemory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of pages that currently have storage allocated
+    pub fn resident_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Drops the contents of every page in `[start_page, end_page)`, so they read
+    /// back as zero the next time they get mapped
+    pub(crate) fn discard(&mut self, start_page: u64, end_page: u64) {
+        let discarded: Vec<u64> = self
+            .pages
+            .range(start_page..end_page)
+            .map(|(page_num, _)| *page_num)
+            .collect();
+        for page_num in discarded {
+            self.pages.remove(&page_num);
+        }
+    }
+}
+
+impl<S: EntryStore> Vmmap<S> {
+    /// Attaches an empty simulated memory to this vmmap, see `SimulatedMemory`
+    pub fn enable_simulated_memory(&mut self) {
+        self.memory = Some(SimulatedMemory::new());
+    }
+
+    /// Reads `buf.len()` bytes at `addr` out of the simulated memory. Fails with
+    /// EFAULT if the range is not mapped readable, and with `Unsupported` if it is
+    /// not anonymous memory or no simulated memory is attached.
+    pub fn read_memory(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), io::Error> {
+        self.validate_user_range(addr, buf.len() as u64, AccessKind::Read)?;
+        self.check_simulated(addr, buf.len() as u64)?;
+
+        let page_size = self.geometry.page_size();
+        let memory = self.memory.as_ref().unwrap();
+
+        let mut copied = 0;
+        while copied < buf.len() {
+            let current_addr = addr + copied as u64;
+            let page_num = self.geometry.addr_to_page(current_addr);
+            let page_offset = (current_addr % page_size) as usize;
+            let chunk = (page_size as usize - page_offset).min(buf.len() - copied);
+
+            let dest = &mut buf[copied..copied + chunk];
+            match memory.pages.get(&page_num) {
+                Some(page) => dest.copy_from_slice(&page[page_offset..page_offset + chunk]),
+                None => dest.fill(0), // never written, reads as zero
+            }
+            copied += chunk;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `data` at `addr` into the simulated memory, allocating zero filled
+    /// pages as needed. Fails the same way as `read_memory`, but requires the
+    /// range to be mapped writable.
+    pub fn write_memory(&mut self, addr: u64, data: &[u8]) -> Result<(), io::Error> {
+        self.validate_user_range(addr, data.len() as u64, AccessKind::Write)?;
+        self.check_simulated(addr, data.len() as u64)?;
+
+        let page_size = self.geometry.page_size();
+        let geometry = self.geometry;
+        let memory = self.memory.as_mut().unwrap();
+
+        let mut copied = 0;
+        while copied < data.len() {
+            let current_addr = addr + copied as u64;
+            let page_num = geometry.addr_to_page(current_addr);
+            let page_offset = (current_addr % page_size) as usize;
+            let chunk = (page_size as usize - page_offset).min(data.len() - copied);
+
+            let page = memory
+                .pages
+                .entry(page_num)
+                .or_insert_with(|| vec![0; page_size as usize].into_boxed_slice());
+            page[page_offset..page_offset + chunk].copy_from_slice(&data[copied..copied + chunk]);
+            copied += chunk;
+        }
+
+        Ok(())
+    }
+
+    /// Makes sure simulated memory is attached and that every page of the
+    /// (already validated) range is backed anonymously
+    fn check_simulated(&self, addr: u64, len: u64) -> Result<(), io::Error> {
+        if self.memory.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "No simulated memory attached to the vmmap",
+            ));
+        }
+        if len == 0 {
+            return Ok(());
+        }
+
+        let first_page = self.geometry.addr_to_page(addr);
+        let last_page = self.geometry.addr_to_page(addr + len - 1);
+        for (_, entry) in self.entries.overlapping(ii(first_page, last_page)) {
+            if entry.backing != MemoryBackingType::Anonymous {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Only anonymous memory can be simulated",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{EFAULT, PAGESIZE, PROT_READ, PROT_WRITE};
+    use crate::types::{MemoryBackingType, VmmapOps};
+    use crate::vmmap::Vmmap;
+
+    fn map(vmmap: &mut Vmmap, page_num: u64, npages: u64, prot: i32, backing: MemoryBackingType) {
+        vmmap
+            .add_entry_with_override(page_num, npages, prot, prot, 0, backing, 0, 0, 1)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_simulated_memory_read_write() {
+        let mut vmmap = Vmmap::new();
+        vmmap.enable_simulated_memory();
+        map(
+            &mut vmmap,
+            0,
+            4,
+            PROT_READ | PROT_WRITE,
+            MemoryBackingType::Anonymous,
+        );
+
+        // untouched pages read as zero without allocating storage
+        let mut buf = [0xff; 16];
+        vmmap.read_memory(100, &mut buf).unwrap();
+        assert_eq!(buf, [0; 16]);
+        assert_eq!(vmmap.memory.as_ref().unwrap().resident_pages(), 0);
+
+        // writes spanning a page boundary land on both pages
+        let addr = PAGESIZE as u64 - 3;
+        vmmap.write_memory(addr, b"hello world").unwrap();
+        let mut buf = [0; 11];
+        vmmap.read_memory(addr, &mut buf).unwrap();
+        assert_eq!(&buf, b"hello world");
+        assert_eq!(vmmap.memory.as_ref().unwrap().resident_pages(), 2);
+
+        // protections are honored
+        vmmap.change_prot(1, 1, PROT_READ).unwrap();
+        assert!(vmmap.write_memory(0, b"x").is_ok());
+        let err = vmmap.write_memory(addr, b"hello world").unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(EFAULT));
+        let err = vmmap
+            .read_memory(4 * PAGESIZE as u64, &mut buf)
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(EFAULT));
+    }
+
+    #[test]
+    fn test_simulated_memory_discarded_on_unmap() {
+        let mut vmmap = Vmmap::new();
+        vmmap.enable_simulated_memory();
+        map(
+            &mut vmmap,
+            0,
+            4,
+            PROT_READ | PROT_WRITE,
+            MemoryBackingType::Anonymous,
+        );
+        vmmap.write_memory(0, &[7; 32]).unwrap();
+
+        vmmap.remove_entry(0, 1).unwrap();
+        map(
+            &mut vmmap,
+            0,
+            1,
+            PROT_READ | PROT_WRITE,
+            MemoryBackingType::Anonymous,
+        );
+
+        let mut buf = [0xff; 32];
+        vmmap.read_memory(0, &mut buf).unwrap();
+        assert_eq!(buf, [0; 32]);
+        assert_eq!(vmmap.memory.as_ref().unwrap().resident_pages(), 0);
+
+        // file backed pages have no simulated contents
+        map(
+            &mut vmmap,
+            8,
+            1,
+            PROT_READ,
+            MemoryBackingType::FileDescriptor(3),
+        );
+        let err = vmmap
+            .read_memory(8 * PAGESIZE as u64, &mut buf)
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    }
+}
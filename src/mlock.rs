@@ -0,0 +1,216 @@
+use std::io;
+
+use nodit::interval::ie;
+
+use crate::constants::{EINVAL, ENOMEM, MCL_CURRENT, MCL_FUTURE};
+use crate::store::EntryStore;
+use crate::types::VmmapOp;
+use crate::vmmap::Vmmap;
+
+impl<S: EntryStore> Vmmap<S> {
+    /// Total number of locked pages, charged against `memlock_limit`
+    pub fn locked_pages(&self) -> u64 {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.locked)
+            .map(|(interval, _)| interval.end() + 1 - interval.start())
+            .sum()
+    }
+
+    /// Whether locking every page of `[start_page, end_page)` keeps the vmmap within
+    /// its memlock limit. Pages of the range that are already locked are not
+    /// charged twice.
+    pub(crate) fn memlock_allows(&self, start_page: u64, end_page: u64) -> bool {
+        let Some(limit) = self.memlock_limit else {
+            return true;
+        };
+
+        let already_locked: u64 = self
+            .entries
+            .overlapping(ie(start_page, end_page))
+            .filter(|(_, entry)| entry.locked)
+            .map(|(interval, _)| {
+                end_page.min(interval.end() + 1) - start_page.max(interval.start())
+            })
+            .sum();
+
+        self.locked_pages() - already_locked + (end_page - start_page) <= limit
+    }
+
+    /// Sets the locked state of every page in the range, splitting the entries at
+    /// the range boundaries. Fails with ENOMEM if part of the range is unmapped.
+    fn set_locked(&mut self, page_num: u64, npages: u64, locked: bool) -> Result<(), io::Error> {
+        if npages == 0 {
+            return Ok(());
+        }
+
+        let end_page = page_num
+            .checked_add(npages)
+            .ok_or_else(|| io::Error::from_raw_os_error(ENOMEM))?;
+        if !self.entries.contains_interval(ie(page_num, end_page)) {
+            return Err(io::Error::from_raw_os_error(ENOMEM));
+        }
+        if locked && !self.memlock_allows(page_num, end_page) {
+            return Err(io::Error::from_raw_os_error(ENOMEM));
+        }
+
+        self.split_at(page_num);
+        self.split_at(end_page);
+        for (_, entry) in self.entries.overlapping_mut(ie(page_num, end_page)) {
+            entry.locked = locked;
+        }
+        // pieces unlocked again join their neighbours
+        self.merge_around(page_num, end_page);
+
+        Ok(())
+    }
+
+    /// Locks `[page_num, page_num + npages)`. Fails with ENOMEM, leaving the vmmap
+    /// untouched, if part of the range is unmapped or the memlock limit would be
+    /// exceeded.
+    pub fn mlock(&mut self, page_num: u64, npages: u64) -> Result<(), io::Error> {
+        let result = self.set_locked(page_num, npages, true);
+        self.record(VmmapOp::Mlock { page_num, npages }, &result);
+        self.debug_validate();
+        result
+    }
+
+    pub fn munlock(&mut self, page_num: u64, npages: u64) -> Result<(), io::Error> {
+        let result = self.set_locked(page_num, npages, false);
+        self.record(VmmapOp::Munlock { page_num, npages }, &result);
+        self.debug_validate();
+        result
+    }
+
+    /// `MCL_CURRENT` locks every existing mapping, `MCL_FUTURE` makes mappings added
+    /// later on start out locked
+    pub fn mlockall(&mut self, flags: i32) -> Result<(), io::Error> {
+        let result = self.set_locked_all(flags);
+        self.record(VmmapOp::MlockAll { flags }, &result);
+        self.debug_validate();
+        result
+    }
+
+    fn set_locked_all(&mut self, flags: i32) -> Result<(), io::Error> {
+        if flags == 0 || flags & !(MCL_CURRENT | MCL_FUTURE) != 0 {
+            return Err(io::Error::from_raw_os_error(EINVAL));
+        }
+
+        if flags & MCL_CURRENT != 0 {
+            let mapped_pages: u64 = self
+                .entries
+                .iter()
+                .map(|(interval, _)| interval.end() + 1 - interval.start())
+                .sum();
+            if self.memlock_limit.is_some_and(|limit| mapped_pages > limit) {
+                return Err(io::Error::from_raw_os_error(ENOMEM));
+            }
+            for (_, entry) in self.entries.iter_mut() {
+                entry.locked = true;
+            }
+        }
+        if flags & MCL_FUTURE != 0 {
+            self.lock_future = true;
+        }
+
+        Ok(())
+    }
+
+    pub fn munlockall(&mut self) {
+        for (_, entry) in self.entries.iter_mut() {
+            entry.locked = false;
+        }
+        self.lock_future = false;
+        self.record(VmmapOp::MunlockAll, &Ok(()));
+        self.debug_validate();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{EAGAIN, ENOMEM, MCL_CURRENT, MCL_FUTURE, PROT_READ};
+    use crate::types::{MemoryBackingType, VmmapOps};
+    use crate::vmmap::Vmmap;
+
+    fn map(vmmap: &mut Vmmap, page_num: u64, npages: u64) -> Result<(), std::io::Error> {
+        vmmap.add_entry_with_override(
+            page_num,
+            npages,
+            PROT_READ,
+            PROT_READ,
+            0,
+            MemoryBackingType::Anonymous,
+            0,
+            0,
+            1,
+        )
+    }
+
+    #[test]
+    fn test_mlock_munlock() {
+        let mut vmmap = Vmmap::new();
+        map(&mut vmmap, 0, 10).unwrap();
+        map(&mut vmmap, 20, 10).unwrap();
+        vmmap.memlock_limit = Some(8);
+
+        vmmap.mlock(2, 4).unwrap();
+        assert_eq!(vmmap.locked_pages(), 4);
+        assert_eq!(vmmap.entries.len(), 4);
+
+        // relocking already locked pages is not charged again
+        vmmap.mlock(0, 8).unwrap();
+        assert_eq!(vmmap.locked_pages(), 8);
+
+        let err = vmmap.mlock(8, 1).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENOMEM));
+
+        // holes are rejected without locking anything
+        vmmap.memlock_limit = None;
+        let err = vmmap.mlock(5, 20).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENOMEM));
+        assert_eq!(vmmap.locked_pages(), 8);
+
+        vmmap.munlock(3, 2).unwrap();
+        assert_eq!(vmmap.locked_pages(), 6);
+        assert!(!vmmap.find_page(3).unwrap().locked);
+        assert!(vmmap.find_page(5).unwrap().locked);
+
+        // unmapping locked pages releases them
+        vmmap.remove_entry(0, 3).unwrap();
+        assert_eq!(vmmap.locked_pages(), 3);
+
+        // unlocking gives the pieces back to the mapping they were split from
+        let mut vmmap = Vmmap::new();
+        map(&mut vmmap, 0, 10).unwrap();
+        vmmap.mlock(2, 3).unwrap();
+        assert_eq!(vmmap.entries.len(), 3);
+        vmmap.munlock(2, 3).unwrap();
+        assert_eq!(vmmap.entries.len(), 1);
+    }
+
+    #[test]
+    fn test_mlockall() {
+        let mut vmmap = Vmmap::new();
+        map(&mut vmmap, 0, 10).unwrap();
+        vmmap.memlock_limit = Some(16);
+
+        vmmap.mlockall(MCL_CURRENT | MCL_FUTURE).unwrap();
+        assert_eq!(vmmap.locked_pages(), 10);
+
+        // future mappings are locked and must fit in the limit
+        map(&mut vmmap, 20, 6).unwrap();
+        assert!(vmmap.find_page(20).unwrap().locked);
+        let err = map(&mut vmmap, 40, 1).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(EAGAIN));
+        assert!(vmmap.find_page(40).is_none());
+
+        // replacing a locked mapping does not count its pages twice
+        map(&mut vmmap, 0, 10).unwrap();
+        assert_eq!(vmmap.locked_pages(), 16);
+
+        vmmap.munlockall();
+        assert_eq!(vmmap.locked_pages(), 0);
+        map(&mut vmmap, 40, 1).unwrap();
+        assert!(!vmmap.find_page(40).unwrap().locked);
+    }
+}
@@ -0,0 +1,267 @@
+use std::io;
+
+use nodit::interval::ie;
+
+use crate::constants::{EBUSY, EINVAL, ENOMEM, MAP_SHARED, MS_ASYNC, MS_INVALIDATE, MS_SYNC};
+use crate::store::EntryStore;
+use crate::types::{FileSpan, MemoryBackingType};
+use crate::vmmap::Vmmap;
+
+impl<S: EntryStore> Vmmap<S> {
+    /// Registers the callback msync hands the file spans to, so the runtime can
+    /// write the real pages back to their files
+    pub fn set_writeback_callback(
+        &mut self,
+        callback: impl FnMut(FileSpan, i32) -> Result<(), io::Error> + Send + 'static,
+    ) {
+        self.writeback_callback = Some(Box::new(callback));
+    }
+
+    /// Returns the file spans backing the shared file mappings of
+    /// `[page_num, page_num + npages)`, in address order. Private and non file
+    /// mappings have nothing to write back and are skipped, as are pages whose
+    /// file offset does not fit in an `i64`. Spans that continue each other in
+    /// the same file are merged.
+    pub fn shared_file_spans(&self, page_num: u64, npages: u64) -> Vec<FileSpan> {
+        let Some(end_page) = page_num.checked_add(npages).filter(|end| *end > page_num) else {
+            return Vec::new();
+        };
+
+        let mut spans: Vec<FileSpan> = Vec::new();
+        for (interval, entry) in self.entries.overlapping(ie(page_num, end_page)) {
+            let MemoryBackingType::FileDescriptor(fd) = entry.backing else {
+                continue;
+            };
+            if entry.flags & MAP_SHARED as i32 == 0 {
+                continue;
+            }
+
+            let start = page_num.max(interval.start());
+            let end = end_page.min(interval.end() + 1);
+            let file_offset = self
+                .geometry
+                .pages_to_bytes(start - entry.page_num)
+                .and_then(|skipped| i64::try_from(skipped).ok())
+                .and_then(|skipped| entry.file_offset.checked_add(skipped));
+            // pages past the largest file offset have nothing to write back to
+            let (Some(file_offset), Some(len)) =
+                (file_offset, self.geometry.pages_to_bytes(end - start))
+            else {
+                continue;
+            };
+            let span = FileSpan {
+                fd,
+                file_offset,
+                len,
+            };
+
+            let span_end = |span: &FileSpan| {
+                i64::try_from(span.len)
+                    .ok()
+                    .and_then(|len| span.file_offset.checked_add(len))
+            };
+            match spans.last_mut() {
+                Some(last) if last.fd == span.fd && span_end(last) == Some(span.file_offset) => {
+                    last.len += span.len
+                }
+                _ => spans.push(span),
+            }
+        }
+        spans
+    }
+
+    /// Flushes the shared file mappings of `[addr, addr + len)` by handing their
+    /// file spans to the writeback callback, `len` being rounded up to whole pages.
+    ///
+    /// Errors are the ones of the syscall: EINVAL for an unaligned address or bad
+    /// flags, ENOMEM if part of the range is unmapped and EBUSY when invalidating
+    /// locked pages. The first error returned by the callback is passed on.
+    pub fn msync(&mut self, addr: u64, len: u64, flags: i32) -> Result<(), io::Error> {
+        if flags & !(MS_ASYNC | MS_INVALIDATE | MS_SYNC) != 0
+            || flags & MS_ASYNC != 0 && flags & MS_SYNC != 0
+            || !addr.is_multiple_of(self.geometry.page_size())
+        {
+            return Err(io::Error::from_raw_os_error(EINVAL));
+        }
+        if len == 0 {
+            return Ok(());
+        }
+
+        let page_num = self.geometry.addr_to_page(addr);
+        let npages = self.geometry.bytes_to_pages(len);
+        let end_page = page_num
+            .checked_add(npages)
+            .ok_or_else(|| io::Error::from_raw_os_error(ENOMEM))?;
+        if !self.entries.contains_interval(ie(page_num, end_page)) {
+            return Err(io::Error::from_raw_os_error(ENOMEM));
+        }
+        if flags & MS_INVALIDATE != 0
+            && self
+                .entries
+                .overlapping(ie(page_num, end_page))
+                .any(|(_, entry)| entry.locked)
+        {
+            return Err(io::Error::from_raw_os_error(EBUSY));
+        }
+
+        let spans = self.shared_file_spans(page_num, npages);
+        if let Some(callback) = &mut self.writeback_callback {
+            for span in spans {
+                callback(span, flags)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::constants::{
+        EBUSY, EINVAL, ENOMEM, MAP_SHARED, MS_ASYNC, MS_INVALIDATE, MS_SYNC, PAGESIZE, PROT_READ,
+        PROT_WRITE,
+    };
+    use crate::types::{FileSpan, MemoryBackingType, VmmapOps};
+    use crate::vmmap::Vmmap;
+
+    const PAGE: u64 = PAGESIZE as u64;
+
+    fn map(vmmap: &mut Vmmap, page_num: u64, npages: u64, flags: i32, backing: MemoryBackingType) {
+        let file_offset = match backing {
+            MemoryBackingType::Anonymous => 0,
+            _ => 0x10000,
+        };
+        vmmap
+            .add_entry_with_override(
+                page_num,
+                npages,
+                PROT_READ | PROT_WRITE,
+                PROT_READ | PROT_WRITE,
+                flags,
+                backing,
+                file_offset,
+                0x100000,
+                1,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_msync_resolves_file_spans() {
+        let mut vmmap = Vmmap::new();
+        map(
+            &mut vmmap,
+            0,
+            4,
+            MAP_SHARED as i32,
+            MemoryBackingType::FileDescriptor(3),
+        );
+        map(&mut vmmap, 4, 2, 0, MemoryBackingType::FileDescriptor(3));
+        map(
+            &mut vmmap,
+            6,
+            2,
+            MAP_SHARED as i32,
+            MemoryBackingType::Anonymous,
+        );
+        map(
+            &mut vmmap,
+            8,
+            4,
+            MAP_SHARED as i32,
+            MemoryBackingType::FileDescriptor(5),
+        );
+
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let written_clone = written.clone();
+        vmmap.set_writeback_callback(move |span, flags| {
+            written_clone.lock().unwrap().push((span, flags));
+            Ok(())
+        });
+
+        // a protection change splits the mapping without splitting the span
+        vmmap.change_prot(2, 1, PROT_READ).unwrap();
+        vmmap.msync(PAGE, 9 * PAGE + 1, MS_SYNC).unwrap();
+        assert_eq!(
+            *written.lock().unwrap(),
+            [
+                (
+                    FileSpan {
+                        fd: 3,
+                        file_offset: 0x10000 + PAGE as i64,
+                        len: 3 * PAGE,
+                    },
+                    MS_SYNC
+                ),
+                (
+                    FileSpan {
+                        fd: 5,
+                        file_offset: 0x10000,
+                        len: 3 * PAGE,
+                    },
+                    MS_SYNC
+                ),
+            ]
+        );
+
+        // offsets past the largest file offset end the span instead of overflowing
+        let mut vmmap = Vmmap::new();
+        let last_page_offset = i64::MAX - PAGE as i64 + 1;
+        for (page_num, file_offset) in [(0, last_page_offset), (2, 0)] {
+            vmmap
+                .add_entry_with_override(
+                    page_num,
+                    2,
+                    PROT_READ,
+                    PROT_READ,
+                    MAP_SHARED as i32,
+                    MemoryBackingType::FileDescriptor(7),
+                    file_offset,
+                    0,
+                    1,
+                )
+                .unwrap();
+        }
+        let span = |file_offset, len| FileSpan {
+            fd: 7,
+            file_offset,
+            len,
+        };
+        assert_eq!(
+            vmmap.shared_file_spans(0, 4),
+            [span(last_page_offset, 2 * PAGE), span(0, 2 * PAGE)]
+        );
+        assert_eq!(vmmap.shared_file_spans(1, 2), [span(0, PAGE)]);
+    }
+
+    #[test]
+    fn test_msync_errors() {
+        let mut vmmap = Vmmap::new();
+        map(
+            &mut vmmap,
+            0,
+            4,
+            MAP_SHARED as i32,
+            MemoryBackingType::FileDescriptor(3),
+        );
+        vmmap.set_writeback_callback(|_, _| Err(std::io::Error::other("disk full")));
+
+        let errno = |result: Result<(), std::io::Error>| result.unwrap_err().raw_os_error();
+        assert_eq!(errno(vmmap.msync(1, PAGE, MS_SYNC)), Some(EINVAL));
+        assert_eq!(
+            errno(vmmap.msync(0, PAGE, MS_SYNC | MS_ASYNC)),
+            Some(EINVAL)
+        );
+        assert_eq!(errno(vmmap.msync(0, PAGE, 0x100)), Some(EINVAL));
+        assert_eq!(errno(vmmap.msync(0, 5 * PAGE, MS_ASYNC)), Some(ENOMEM));
+
+        vmmap.mlock(3, 1).unwrap();
+        assert_eq!(errno(vmmap.msync(0, 4 * PAGE, MS_INVALIDATE)), Some(EBUSY));
+
+        // write back failures reach the caller
+        let err = vmmap.msync(0, PAGE, MS_ASYNC).unwrap_err();
+        assert_eq!(err.to_string(), "disk full");
+    }
+}
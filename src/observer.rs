@@ -0,0 +1,144 @@
+use crate::store::EntryStore;
+use crate::types::VmmapEvent;
+use crate::vmmap::Vmmap;
+
+impl<S: EntryStore> Vmmap<S> {
+    /// Registers `observer` to be called with every change made to the entries
+    /// from now on. Returns an id for `remove_observer`.
+    pub fn add_observer(&mut self, observer: impl FnMut(&VmmapEvent) + Send + 'static) -> u64 {
+        let id = self.next_observer_id;
+        self.next_observer_id += 1;
+        self.observers.push((id, Box::new(observer)));
+        id
+    }
+
+    /// Unregisters the observer with the given id, returning whether it was found
+    pub fn remove_observer(&mut self, id: u64) -> bool {
+        let before = self.observers.len();
+        self.observers.retain(|(observer_id, _)| *observer_id != id);
+        self.observers.len() != before
+    }
+
+    /// Whether any observer is registered. Events carry copies of the entries, so
+    /// callers skip building them when nobody listens.
+    pub(crate) fn observed(&self) -> bool {
+        !self.observers.is_empty()
+    }
+
+    /// Hands `events` to every observer, in registration order. While a
+    /// transaction is being applied they are held back until it commits.
+    pub(crate) fn notify(&mut self, events: Vec<VmmapEvent>) {
+        if let Some(pending) = &mut self.pending_events {
+            pending.extend(events);
+            return;
+        }
+        for event in &events {
+            for (_, observer) in &mut self.observers {
+                observer(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use nodit::interval::ie;
+
+    use crate::constants::{PROT_READ, PROT_WRITE};
+    use crate::types::{MemoryBackingType, VmmapEvent, VmmapOps};
+    use crate::vmmap::Vmmap;
+
+    fn record(vmmap: &mut Vmmap) -> Arc<Mutex<Vec<VmmapEvent>>> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = events.clone();
+        vmmap.add_observer(move |event| events_clone.lock().unwrap().push(event.clone()));
+        events
+    }
+
+    /// Event kinds with their intervals as `[start, end)` pairs
+    fn summary(events: &[VmmapEvent]) -> Vec<(&'static str, u64, u64)> {
+        events
+            .iter()
+            .map(|event| match event {
+                VmmapEvent::Mapped { interval, .. } => ("mapped", interval),
+                VmmapEvent::Unmapped { interval, .. } => ("unmapped", interval),
+                VmmapEvent::ProtectionChanged { interval, .. } => ("prot", interval),
+                VmmapEvent::Split { interval, .. } => ("split", interval),
+                VmmapEvent::Merged { interval, .. } => ("merged", interval),
+            })
+            .map(|(kind, interval)| (kind, interval.start(), interval.end() + 1))
+            .collect()
+    }
+
+    fn map(vmmap: &mut Vmmap, page_num: u64, npages: u64) {
+        vmmap
+            .add_entry_with_override(
+                page_num,
+                npages,
+                PROT_READ,
+                PROT_READ | PROT_WRITE,
+                0,
+                MemoryBackingType::Anonymous,
+                0,
+                0,
+                1,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_observers_receive_events() {
+        let mut vmmap = Vmmap::new();
+        let events = record(&mut vmmap);
+
+        map(&mut vmmap, 0, 10);
+        map(&mut vmmap, 8, 4);
+        vmmap.remove_entry(4, 2).unwrap();
+        assert_eq!(
+            summary(&events.lock().unwrap()),
+            [
+                ("mapped", 0, 10),
+                ("unmapped", 8, 10),
+                ("mapped", 8, 12),
+                ("unmapped", 4, 6),
+            ]
+        );
+
+        events.lock().unwrap().clear();
+        vmmap.change_prot(1, 2, PROT_READ | PROT_WRITE).unwrap();
+        vmmap.change_prot(1, 2, PROT_READ).unwrap();
+        assert_eq!(
+            summary(&events.lock().unwrap()),
+            [
+                ("split", 0, 4),
+                ("split", 1, 4),
+                ("prot", 1, 3),
+                ("prot", 1, 3),
+                ("merged", 0, 4),
+            ]
+        );
+        match &events.lock().unwrap()[4] {
+            VmmapEvent::Merged { intervals, .. } => {
+                assert_eq!(intervals, &[ie(0, 1), ie(1, 3), ie(3, 4)])
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+        assert_eq!(vmmap.entries.len(), 3);
+    }
+
+    #[test]
+    fn test_remove_observer() {
+        let mut vmmap = Vmmap::new();
+        let events = Arc::new(Mutex::new(0));
+        let events_clone = events.clone();
+        let id = vmmap.add_observer(move |_| *events_clone.lock().unwrap() += 1);
+
+        map(&mut vmmap, 0, 10);
+        assert!(vmmap.remove_observer(id));
+        assert!(!vmmap.remove_observer(id));
+        vmmap.remove_entry(0, 10).unwrap();
+        assert_eq!(*events.lock().unwrap(), 1);
+    }
+}
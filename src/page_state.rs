@@ -0,0 +1,263 @@
+use std::io;
+
+use nodit::interval::ie;
+
+use crate::constants::EFAULT;
+use crate::store::EntryStore;
+use crate::types::{PageState, PageTracking};
+use crate::vmmap::Vmmap;
+
+impl PageTracking {
+    /// Creates bitmaps for `npages` pages with every page clean and not accessed
+    pub fn new(npages: u64) -> Self {
+        let words = npages.div_ceil(64) as usize;
+        PageTracking {
+            accessed: vec![0; words],
+            dirty: vec![0; words],
+        }
+    }
+
+    fn bitmap(&self, state: PageState) -> &Vec<u64> {
+        match state {
+            PageState::Accessed => &self.accessed,
+            PageState::Dirty => &self.dirty,
+        }
+    }
+
+    fn bitmap_mut(&mut self, state: PageState) -> &mut Vec<u64> {
+        match state {
+            PageState::Accessed => &mut self.accessed,
+            PageState::Dirty => &mut self.dirty,
+        }
+    }
+
+    pub fn get(&self, state: PageState, index: u64) -> bool {
+        self.bitmap(state)[(index / 64) as usize] & (1 << (index % 64)) != 0
+    }
+
+    pub fn set(&mut self, state: PageState, index: u64) {
+        self.bitmap_mut(state)[(index / 64) as usize] |= 1 << (index % 64);
+    }
+
+    pub fn clear(&mut self, state: PageState, index: u64) {
+        self.bitmap_mut(state)[(index / 64) as usize] &= !(1 << (index % 64));
+    }
+
+    /// Copy of the bitmaps for the `npages` pages starting at index `start`, for
+    /// the piece of a mapping split off at that page
+    pub(crate) fn sliced(&self, start: u64, npages: u64) -> Self {
+        let mut sliced = PageTracking::new(npages);
+        for index in 0..npages {
+            for state in [PageState::Accessed, PageState::Dirty] {
+                if self.get(state, start + index) {
+                    sliced.set(state, index);
+                }
+            }
+        }
+        sliced
+    }
+
+    /// Copy of the bitmaps for a mapping grown to `npages` pages, whose old first
+    /// page is now at index `shift`. New pages start out clean and not accessed.
+    pub(crate) fn grown(&self, shift: u64, npages: u64) -> Self {
+        let mut grown = PageTracking::new(npages);
+        let old_npages = (self.accessed.len() as u64 * 64).min(npages - shift);
+        for index in 0..old_npages {
+            for state in [PageState::Accessed, PageState::Dirty] {
+                if self.get(state, index) {
+                    grown.set(state, index + shift);
+                }
+            }
+        }
+        grown
+    }
+
+    /// Bitmaps of a mapping of `npages` pages joined with the mapping tracked by
+    /// `upper` right after it, `total` pages in all
+    pub(crate) fn joined(&self, npages: u64, upper: &PageTracking, total: u64) -> Self {
+        let mut joined = self.grown(0, total);
+        for index in 0..total - npages {
+            for state in [PageState::Accessed, PageState::Dirty] {
+                if upper.get(state, index) {
+                    joined.set(state, npages + index);
+                }
+            }
+        }
+        joined
+    }
+}
+
+impl<S: EntryStore> Vmmap<S> {
+    /// Records `state` for every page in `[page_num, page_num + npages)`. Meant to be
+    /// called by the runtime's fault handler or write barrier. Marking a page dirty
+    /// also marks it accessed. Fails with EFAULT, without marking anything, if part
+    /// of the range is not mapped.
+    pub fn mark_pages(
+        &mut self,
+        page_num: u64,
+        npages: u64,
+        state: PageState,
+    ) -> Result<(), io::Error> {
+        if npages == 0 {
+            return Ok(());
+        }
+
+        let end_page = page_num
+            .checked_add(npages)
+            .ok_or_else(|| io::Error::from_raw_os_error(EFAULT))?;
+        if !self.entries.contains_interval(ie(page_num, end_page)) {
+            return Err(io::Error::from_raw_os_error(EFAULT));
+        }
+
+        for (interval, entry) in self.entries.overlapping_mut(ie(page_num, end_page)) {
+            let (ent_page_num, ent_npages) = (entry.page_num, entry.npages);
+            let tracking = entry
+                .page_tracking
+                .get_or_insert_with(|| PageTracking::new(ent_npages));
+
+            for page in page_num.max(interval.start())..end_page.min(interval.end() + 1) {
+                tracking.set(state, page - ent_page_num);
+                if state == PageState::Dirty {
+                    tracking.set(PageState::Accessed, page - ent_page_num);
+                }
+            }
+        }
+
+        self.debug_validate();
+        Ok(())
+    }
+
+    /// Returns the pages in `[page_num, page_num + npages)` that have `state` recorded,
+    /// in ascending order. Unmapped pages are skipped.
+    pub fn pages_in_state(&self, page_num: u64, npages: u64, state: PageState) -> Vec<u64> {
+        let Some(end_page) = page_num.checked_add(npages).filter(|end| *end > page_num) else {
+            return Vec::new();
+        };
+
+        let mut pages = Vec::new();
+        for (interval, entry) in self.entries.overlapping(ie(page_num, end_page)) {
+            let Some(tracking) = &entry.page_tracking else {
+                continue;
+            };
+            pages.extend(
+                (page_num.max(interval.start())..end_page.min(interval.end() + 1))
+                    .filter(|page| tracking.get(state, page - entry.page_num)),
+            );
+        }
+        pages
+    }
+
+    /// Forgets `state` for every mapped page in `[page_num, page_num + npages)`, e.g.
+    /// once a checkpoint has captured the dirty pages
+    pub fn clear_pages(&mut self, page_num: u64, npages: u64, state: PageState) {
+        let Some(end_page) = page_num.checked_add(npages).filter(|end| *end > page_num) else {
+            return;
+        };
+
+        for (interval, entry) in self.entries.overlapping_mut(ie(page_num, end_page)) {
+            let ent_page_num = entry.page_num;
+            let Some(tracking) = &mut entry.page_tracking else {
+                continue;
+            };
+            for page in page_num.max(interval.start())..end_page.min(interval.end() + 1) {
+                tracking.clear(state, page - ent_page_num);
+            }
+        }
+
+        self.debug_validate();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{EFAULT, PROT_READ, PROT_WRITE};
+    use crate::types::{MemoryBackingType, PageState, VmmapOps};
+    use crate::vmmap::Vmmap;
+
+    fn create_vmmap() -> Vmmap {
+        let mut vmmap = Vmmap::new();
+        vmmap
+            .add_entry_with_override(
+                0,
+                100,
+                PROT_READ | PROT_WRITE,
+                PROT_READ | PROT_WRITE,
+                0,
+                MemoryBackingType::Anonymous,
+                0,
+                0,
+                1,
+            )
+            .unwrap();
+        vmmap
+    }
+
+    #[test]
+    fn test_mark_and_clear_pages() {
+        let mut vmmap = create_vmmap();
+
+        vmmap.mark_pages(10, 3, PageState::Dirty).unwrap();
+        vmmap.mark_pages(70, 1, PageState::Accessed).unwrap();
+
+        assert_eq!(vmmap.pages_in_state(0, 100, PageState::Dirty), [10, 11, 12]);
+        assert_eq!(
+            vmmap.pages_in_state(0, 100, PageState::Accessed),
+            [10, 11, 12, 70]
+        );
+        assert_eq!(vmmap.pages_in_state(11, 50, PageState::Dirty), [11, 12]);
+
+        vmmap.clear_pages(0, 11, PageState::Dirty);
+        assert_eq!(vmmap.pages_in_state(0, 100, PageState::Dirty), [11, 12]);
+        assert_eq!(
+            vmmap.pages_in_state(0, 100, PageState::Accessed),
+            [10, 11, 12, 70]
+        );
+
+        // nothing is marked if part of the range is unmapped
+        let err = vmmap.mark_pages(95, 10, PageState::Dirty).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(EFAULT));
+        assert!(vmmap.pages_in_state(95, 5, PageState::Dirty).is_empty());
+    }
+
+    #[test]
+    fn test_page_state_across_splits_and_unmaps() {
+        let mut vmmap = create_vmmap();
+        vmmap.mark_pages(0, 100, PageState::Dirty).unwrap();
+
+        // protection changes split the entry but keep the recorded state
+        vmmap.change_prot(20, 10, PROT_READ).unwrap();
+        assert_eq!(vmmap.entries.len(), 3);
+        assert_eq!(
+            vmmap.pages_in_state(18, 4, PageState::Dirty),
+            [18, 19, 20, 21]
+        );
+
+        // clearing one piece leaves the others alone
+        vmmap.clear_pages(20, 10, PageState::Dirty);
+        assert_eq!(
+            vmmap.pages_in_state(18, 14, PageState::Dirty),
+            [18, 19, 30, 31]
+        );
+
+        // unmapping drops the state, a new mapping starts out clean
+        vmmap.remove_entry(40, 10).unwrap();
+        vmmap
+            .add_entry_with_override(
+                40,
+                5,
+                PROT_READ,
+                PROT_READ,
+                0,
+                MemoryBackingType::Anonymous,
+                0,
+                0,
+                1,
+            )
+            .unwrap();
+        assert_eq!(
+            vmmap.pages_in_state(38, 14, PageState::Dirty),
+            [38, 39, 50, 51]
+        );
+        assert_eq!(vmmap.pages_in_state(38, 12, PageState::Accessed), [38, 39]);
+    }
+}
@@ -0,0 +1,159 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+use nodit::{interval::ie, Interval};
+
+use crate::utils::SeededRng;
+
+/// Policy used by the vmmap to decide where a non-fixed mapping gets placed.
+///
+/// `gaps` holds the free intervals of the (bounded) address space in ascending
+/// order. Implementations return `npages` pages starting on a multiple of `align`
+/// that lie entirely inside one of the gaps, or None if no gap can fit them.
+/// Stateful policies keep their state behind an atomic or a `Mutex` since placement
+/// searches only borrow the vmmap immutably, and the vmmap must stay `Send`.
+pub trait PlacementStrategy: Send {
+    fn place(&self, gaps: &[Interval<u64>], npages: u64, align: u64) -> Option<Interval<u64>>;
+}
+
+/// Returns the lowest and highest aligned start page at which `npages` pages fit
+/// inside `gap`, if any
+fn aligned_starts(gap: &Interval<u64>, npages: u64, align: u64) -> Option<(u64, u64)> {
+    let first = gap.start().checked_next_multiple_of(align)?;
+    let last = (gap.end() + 1).checked_sub(npages)?;
+    let last = last - last % align;
+    if first > last {
+        return None;
+    }
+    Some((first, last))
+}
+
+/// Lowest gap that fits, placed at its bottom
+pub struct FirstFit;
+
+impl PlacementStrategy for FirstFit {
+    fn place(&self, gaps: &[Interval<u64>], npages: u64, align: u64) -> Option<Interval<u64>> {
+        gaps.iter()
+            .find_map(|gap| aligned_starts(gap, npages, align))
+            .map(|(start, _)| ie(start, start + npages))
+    }
+}
+
+/// Smallest gap that fits (lowest one on ties), placed at its bottom
+pub struct BestFit;
+
+impl PlacementStrategy for BestFit {
+    fn place(&self, gaps: &[Interval<u64>], npages: u64, align: u64) -> Option<Interval<u64>> {
+        gaps.iter()
+            .filter_map(|gap| Some((gap.end() - gap.start(), aligned_starts(gap, npages, align)?)))
+            .min_by_key(|(gap_size, _)| *gap_size)
+            .map(|(_, (start, _))| ie(start, start + npages))
+    }
+}
+
+/// First fit that resumes searching where the previous placement ended,
+/// wrapping around to the bottom once it runs out of gaps
+pub struct NextFit {
+    cursor: AtomicU64,
+}
+
+impl NextFit {
+    pub fn new() -> Self {
+        NextFit {
+            cursor: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Default for NextFit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PlacementStrategy for NextFit {
+    fn place(&self, gaps: &[Interval<u64>], npages: u64, align: u64) -> Option<Interval<u64>> {
+        let cursor = self.cursor.load(Ordering::Relaxed);
+
+        let above_cursor = gaps.iter().filter(|gap| gap.end() >= cursor).map(|gap| {
+            // trim the gap the cursor falls into
+            ie(gap.start().max(cursor), gap.end() + 1)
+        });
+        let wrapped = gaps.iter().copied();
+
+        let (start, _) = above_cursor
+            .chain(wrapped)
+            .find_map(|gap| aligned_starts(&gap, npages, align))?;
+
+        self.cursor.store(start + npages, Ordering::Relaxed);
+        Some(ie(start, start + npages))
+    }
+}
+
+/// Highest gap below `mmap_base` that fits, placed at its top. This mirrors how
+/// Linux lays out mappings downwards from below the stack
+pub struct TopDown {
+    pub mmap_base: u64,
+}
+
+impl TopDown {
+    pub fn new(mmap_base: u64) -> Self {
+        TopDown { mmap_base }
+    }
+}
+
+impl PlacementStrategy for TopDown {
+    fn place(&self, gaps: &[Interval<u64>], npages: u64, align: u64) -> Option<Interval<u64>> {
+        gaps.iter()
+            .rev()
+            .filter(|gap| gap.start() < self.mmap_base)
+            .map(|gap| ie(gap.start(), (gap.end() + 1).min(self.mmap_base)))
+            .find_map(|gap| aligned_starts(&gap, npages, align))
+            .map(|(_, start)| ie(start, start + npages))
+    }
+}
+
+/// Uniformly random aligned slot out of every slot that fits, driven by a
+/// seedable generator so that placements can be reproduced
+pub struct Random {
+    rng: Mutex<SeededRng>,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random {
+            rng: Mutex::new(SeededRng::new(seed)),
+        }
+    }
+}
+
+impl PlacementStrategy for Random {
+    fn place(&self, gaps: &[Interval<u64>], npages: u64, align: u64) -> Option<Interval<u64>> {
+        // (first aligned start, number of aligned starts) for every gap that fits
+        let candidates: Vec<(u64, u64)> = gaps
+            .iter()
+            .filter_map(|gap| aligned_starts(gap, npages, align))
+            .map(|(first, last)| (first, (last - first) / align + 1))
+            .collect();
+
+        let total: u64 = candidates.iter().map(|(_, count)| count).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut pick = self
+            .rng
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .below(total);
+        for (first, count) in candidates {
+            if pick < count {
+                let start = first + pick * align;
+                return Some(ie(start, start + npages));
+            }
+            pick -= count;
+        }
+
+        None
+    }
+}
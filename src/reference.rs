@@ -0,0 +1,582 @@
+use std::io;
+use std::ops::Range;
+
+use nodit::interval::ie;
+use nodit::Interval;
+
+use crate::constants::{EACCES, EEXIST, MAP_SHARED, PROT_EXEC, PROT_NONE, PROT_READ, PROT_WRITE};
+use crate::diff::PageAttributes;
+use crate::types::{
+    AccessKind, Advice, FaultClass, MemoryBackingType, VmmapEntry, VmmapGeometry, VmmapOp, VmmapOps,
+};
+use crate::utils::SeededRng;
+use crate::validate::check_mapping;
+use crate::vmmap::{access_prot, Vmmap};
+
+/// Deliberately simple `VmmapOps` implementation keeping one slot per page of a
+/// small address space starting at page 0. Every operation walks the pages one
+/// by one, which makes it slow but easy to trust, so it serves as an oracle for
+/// `Vmmap`, see `run_differential`.
+pub struct PageArrayVmmap {
+    pages: Vec<Option<VmmapEntry>>, // indexed by page number
+    // consecutive pages holding equal entries, rebuilt after every change so the
+    // interval based parts of `VmmapOps` have something to point into
+    runs: Vec<(Interval<u64>, VmmapEntry)>,
+    runs_modified: bool, // runs were handed out mutably, copy them back before changing pages
+    geometry: VmmapGeometry,
+}
+
+impl PageArrayVmmap {
+    pub fn new(npages: u64) -> Self {
+        PageArrayVmmap {
+            pages: vec![None; npages as usize],
+            runs: Vec::new(),
+            runs_modified: false,
+            geometry: VmmapGeometry::default(),
+        }
+    }
+
+    /// Indices of `[page_num, page_num + npages)`, clamped to the page array
+    fn clamped(&self, page_num: u64, npages: u64) -> Range<usize> {
+        let len = self.pages.len() as u64;
+        let end = page_num.saturating_add(npages).min(len);
+        page_num.min(len) as usize..end as usize
+    }
+
+    fn slot(&self, page_num: u64) -> Option<&VmmapEntry> {
+        self.pages.get(usize::try_from(page_num).ok()?)?.as_ref()
+    }
+
+    fn sync_pages(&mut self) {
+        if !self.runs_modified {
+            return;
+        }
+        for (interval, entry) in &self.runs {
+            for page in interval.start()..=interval.end() {
+                self.pages[page as usize] = Some(entry.clone());
+            }
+        }
+        self.runs_modified = false;
+    }
+
+    fn rebuild_runs(&mut self) {
+        self.runs.clear();
+        for (page, slot) in self.pages.iter().enumerate() {
+            let Some(entry) = slot else {
+                continue;
+            };
+            let page = page as u64;
+            match self.runs.last_mut() {
+                Some((interval, run_entry)) if interval.end() + 1 == page && run_entry == entry => {
+                    *interval = ie(interval.start(), page + 1)
+                }
+                _ => self.runs.push((ie(page, page + 1), entry.clone())),
+            }
+        }
+    }
+
+    fn run_index(&self, page_num: u64) -> Option<usize> {
+        self.runs
+            .iter()
+            .position(|(interval, _)| interval.start() <= page_num && page_num <= interval.end())
+    }
+
+    /// Lowest start at or above `hint` that is a multiple of `align` and begins
+    /// `npages` free pages
+    fn first_fit(&self, npages: u64, align: u64, hint: u64) -> Option<Interval<u64>> {
+        let len = self.pages.len() as u64;
+        let mut start = hint.checked_next_multiple_of(align)?;
+        while start.checked_add(npages)? <= len {
+            if (start..start + npages).all(|page| self.slot(page).is_none()) {
+                return Some(ie(start, start + npages));
+            }
+            start += align;
+        }
+        None
+    }
+}
+
+/// Protection with the implicit PROT_READ of any accessible mapping
+fn effective_prot(prot: i32) -> i32 {
+    if prot & (PROT_EXEC | PROT_READ | PROT_WRITE) != PROT_NONE {
+        prot | PROT_READ
+    } else {
+        prot
+    }
+}
+
+impl VmmapOps for PageArrayVmmap {
+    fn update(
+        &mut self,
+        page_num: u64,
+        npages: u64,
+        prot: i32,
+        maxprot: i32,
+        flags: i32,
+        backing: MemoryBackingType,
+        remove: bool,
+        file_offset: i64,
+        file_size: i64,
+        cage_id: u64,
+    ) -> Result<(), io::Error> {
+        if npages == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Number of pages cannot be zero",
+            ));
+        }
+        if page_num.saturating_add(npages) > self.pages.len() as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Range lies outside of the page array",
+            ));
+        }
+        if !remove {
+            check_mapping(prot, maxprot, flags, backing, file_offset, &self.geometry)?;
+        }
+
+        self.sync_pages();
+        let entry = (!remove).then(|| VmmapEntry {
+            page_num,
+            npages,
+            prot,
+            maxprot,
+            flags,
+            removed: false,
+            file_offset,
+            file_size,
+            cage_id,
+            backing,
+            page_tracking: None,
+            locked: false,
+            advice: Advice::default(),
+        });
+        for page in self.clamped(page_num, npages) {
+            self.pages[page] = entry.clone();
+        }
+        self.rebuild_runs();
+        Ok(())
+    }
+
+    fn add_entry(&mut self, vmmap_entry_ref: VmmapEntry) -> Result<(), io::Error> {
+        if vmmap_entry_ref.npages == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Number of pages cannot be zero",
+            ));
+        }
+        check_mapping(
+            vmmap_entry_ref.prot,
+            vmmap_entry_ref.maxprot,
+            vmmap_entry_ref.flags,
+            vmmap_entry_ref.backing,
+            vmmap_entry_ref.file_offset,
+            &self.geometry,
+        )?;
+        let range = self.clamped(vmmap_entry_ref.page_num, vmmap_entry_ref.npages);
+        if range.len() as u64 != vmmap_entry_ref.npages {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Range lies outside of the page array",
+            ));
+        }
+        if self.pages[range.clone()].iter().any(Option::is_some) {
+            return Err(io::Error::from_raw_os_error(EEXIST));
+        }
+
+        self.sync_pages();
+        for page in range {
+            self.pages[page] = Some(vmmap_entry_ref.clone());
+        }
+        self.rebuild_runs();
+        Ok(())
+    }
+
+    fn add_entry_with_override(
+        &mut self,
+        page_num: u64,
+        npages: u64,
+        prot: i32,
+        maxprot: i32,
+        flags: i32,
+        backing: MemoryBackingType,
+        file_offset: i64,
+        file_size: i64,
+        cage_id: u64,
+    ) -> Result<(), io::Error> {
+        self.update(
+            page_num,
+            npages,
+            prot,
+            maxprot,
+            flags,
+            backing,
+            false,
+            file_offset,
+            file_size,
+            cage_id,
+        )
+    }
+
+    fn change_prot(&mut self, page_num: u64, npages: u64, new_prot: i32) -> Result<(), io::Error> {
+        let range = self.clamped(page_num, npages);
+        if self.pages[range]
+            .iter()
+            .flatten()
+            .any(|entry| new_prot & !entry.maxprot != 0)
+        {
+            return Err(io::Error::from_raw_os_error(EACCES));
+        }
+
+        self.sync_pages();
+        for page in self.clamped(page_num, npages) {
+            if let Some(entry) = &mut self.pages[page] {
+                entry.prot = new_prot;
+            }
+        }
+        self.rebuild_runs();
+        Ok(())
+    }
+
+    fn remove_entry(&mut self, page_num: u64, npages: u64) -> Result<(), io::Error> {
+        self.update(
+            page_num,
+            npages,
+            0,
+            0,
+            0,
+            MemoryBackingType::None,
+            true,
+            0,
+            0,
+            0,
+        )
+    }
+
+    fn check_existing_mapping(&self, page_num: u64, npages: u64, prot: i32) -> bool {
+        npages > 0
+            && (page_num..page_num.saturating_add(npages)).all(|page| {
+                self.slot(page)
+                    .is_some_and(|entry| prot & !entry.maxprot == 0)
+            })
+    }
+
+    fn check_addr_mapping(&mut self, page_num: u64, npages: u64, prot: i32) -> Option<u64> {
+        let end_page = page_num.checked_add(npages).filter(|end| *end > page_num)?;
+        for page in page_num..end_page {
+            let entry = self.slot(page)?;
+            if prot & !effective_prot(entry.prot) != 0 {
+                return None;
+            }
+        }
+        let (interval, _) = &self.runs[self.run_index(end_page - 1)?];
+        Some(interval.end() + 1)
+    }
+
+    fn classify_fault(&self, addr: u64, access: AccessKind) -> FaultClass {
+        let page_num = self.geometry.addr_to_page(addr);
+        let Some(entry) = self.slot(page_num) else {
+            return FaultClass::NotMapped;
+        };
+        if access_prot(access) & !effective_prot(entry.prot) != 0 {
+            return FaultClass::ProtectionViolation(entry.prot);
+        }
+        if let MemoryBackingType::FileDescriptor(_) = entry.backing {
+            let offset = self
+                .geometry
+                .pages_to_bytes(page_num - entry.page_num)
+                .and_then(|bytes| i64::try_from(bytes).ok())
+                .and_then(|bytes| entry.file_offset.checked_add(bytes));
+            if offset.is_none_or(|offset| offset >= entry.file_size) {
+                return FaultClass::BeyondFileEnd;
+            }
+        }
+        FaultClass::Allowed
+    }
+
+    fn find_page(&self, page_num: u64) -> Option<&VmmapEntry> {
+        self.slot(page_num)
+    }
+
+    fn find_page_mut(&mut self, page_num: u64) -> Option<&mut VmmapEntry> {
+        let index = self.run_index(page_num)?;
+        self.runs_modified = true;
+        Some(&mut self.runs[index].1)
+    }
+
+    fn find_page_iter(
+        &self,
+        page_num: u64,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &VmmapEntry)> {
+        self.runs
+            .iter()
+            .filter(move |(interval, _)| interval.end() >= page_num)
+            .map(|(interval, entry)| (interval, entry))
+    }
+
+    fn find_page_iter_mut(
+        &mut self,
+        page_num: u64,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &mut VmmapEntry)> {
+        self.runs_modified = true;
+        self.runs
+            .iter_mut()
+            .filter(move |(interval, _)| interval.end() >= page_num)
+            .map(|(interval, entry)| (&*interval, entry))
+    }
+
+    fn first_entry(&self) -> Option<(&Interval<u64>, &VmmapEntry)> {
+        self.runs.first().map(|(interval, entry)| (interval, entry))
+    }
+
+    fn last_entry(&self) -> Option<(&Interval<u64>, &VmmapEntry)> {
+        self.runs.last().map(|(interval, entry)| (interval, entry))
+    }
+
+    fn double_ended_iter(&self) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &VmmapEntry)> {
+        self.runs.iter().map(|(interval, entry)| (interval, entry))
+    }
+
+    fn double_ended_iter_mut(
+        &mut self,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &mut VmmapEntry)> {
+        self.runs_modified = true;
+        self.runs
+            .iter_mut()
+            .map(|(interval, entry)| (&*interval, entry))
+    }
+
+    fn find_space(&self, npages: u64) -> Option<Interval<u64>> {
+        self.find_space_above_hint(npages, 0)
+    }
+
+    fn find_space_above_hint(&self, npages: u64, hint: u64) -> Option<Interval<u64>> {
+        if npages == 0 {
+            return None;
+        }
+        self.first_fit(npages, 1, hint)
+    }
+
+    fn find_map_space(&self, num_pages: u64) -> Option<Interval<u64>> {
+        self.find_map_space_with_hint(num_pages, 0)
+    }
+
+    fn find_map_space_with_hint(&self, num_pages: u64, hint: u64) -> Option<Interval<u64>> {
+        if num_pages == 0 {
+            return None;
+        }
+        let rounded_num_pages = self.geometry.round_page_num_up_to_map_multiple(num_pages)?;
+        self.first_fit(rounded_num_pages, self.geometry.pages_per_map(), hint)
+    }
+}
+
+/// Size of the address space `run_differential` works on
+const DIFFERENTIAL_PAGES: u64 = 256;
+
+fn apply(target: &mut impl VmmapOps, op: &VmmapOp) -> Result<(), io::Error> {
+    match *op {
+        VmmapOp::Map {
+            page_num,
+            npages,
+            prot,
+            maxprot,
+            flags,
+            backing,
+            file_offset,
+            file_size,
+            cage_id,
+        } => target.add_entry_with_override(
+            page_num,
+            npages,
+            prot,
+            maxprot,
+            flags,
+            backing,
+            file_offset,
+            file_size,
+            cage_id,
+        ),
+        VmmapOp::Insert {
+            page_num,
+            npages,
+            prot,
+            maxprot,
+            flags,
+            backing,
+            file_offset,
+            file_size,
+            cage_id,
+        } => target.add_entry(VmmapEntry::new(
+            page_num,
+            npages,
+            prot,
+            maxprot,
+            flags,
+            false,
+            file_offset,
+            file_size,
+            cage_id,
+            backing,
+        )),
+        VmmapOp::Unmap { page_num, npages } => target.remove_entry(page_num, npages),
+        VmmapOp::Protect {
+            page_num,
+            npages,
+            prot,
+        } => target.change_prot(page_num, npages, prot),
+        _ => panic!("{op:?} is not part of VmmapOps"),
+    }
+}
+
+/// Random length of up to `max` pages, now and then zero
+fn random_npages(rng: &mut SeededRng, max: u64) -> u64 {
+    if rng.below(16) == 0 {
+        0
+    } else {
+        1 + rng.below(max)
+    }
+}
+
+fn random_op(rng: &mut SeededRng) -> VmmapOp {
+    let npages = random_npages(rng, 24);
+    let page_num = rng.below(DIFFERENTIAL_PAGES - npages + 1);
+    let prot = rng.below(8) as i32;
+    match rng.below(4) {
+        0 | 1 => {
+            let (backing, file_offset) = if rng.below(2) == 0 {
+                (MemoryBackingType::Anonymous, 0)
+            } else {
+                (
+                    MemoryBackingType::FileDescriptor(3 + rng.below(2)),
+                    (rng.below(16) << 12) as i64,
+                )
+            };
+            let maxprot = prot | rng.below(8) as i32;
+            let flags = if rng.below(2) == 0 {
+                MAP_SHARED as i32
+            } else {
+                0
+            };
+            let file_size = (rng.below(48) << 12) as i64;
+            if rng.below(4) == 0 {
+                VmmapOp::Insert {
+                    page_num,
+                    npages,
+                    prot,
+                    maxprot,
+                    flags,
+                    backing,
+                    file_offset,
+                    file_size,
+                    cage_id: 1,
+                }
+            } else {
+                VmmapOp::Map {
+                    page_num,
+                    npages,
+                    prot,
+                    maxprot,
+                    flags,
+                    backing,
+                    file_offset,
+                    file_size,
+                    cage_id: 1,
+                }
+            }
+        }
+        2 => VmmapOp::Unmap { page_num, npages },
+        _ => VmmapOp::Protect {
+            page_num,
+            npages,
+            prot,
+        },
+    }
+}
+
+/// Runs `steps` random operations seeded by `seed` against both `Vmmap` and
+/// `PageArrayVmmap`, comparing after every step what each page is mapped as,
+/// permission checks, fault classification and first fit placement. Returns a
+/// description of the first divergence.
+pub fn run_differential(seed: u64, steps: usize) -> Result<(), String> {
+    let mut rng = SeededRng::new(seed);
+    let mut vmmap = Vmmap::with_bounds(0, DIFFERENTIAL_PAGES);
+    let mut reference = PageArrayVmmap::new(DIFFERENTIAL_PAGES);
+    let geometry = vmmap.geometry;
+
+    for step in 0..steps {
+        let op = random_op(&mut rng);
+        let fail = |what: String| Err(format!("seed {seed}, step {step} ({op:?}): {what}"));
+
+        let (actual, expected) = (apply(&mut vmmap, &op), apply(&mut reference, &op));
+        if actual.is_ok() != expected.is_ok() {
+            return fail(format!("returned {actual:?}, reference {expected:?}"));
+        }
+
+        for page in 0..DIFFERENTIAL_PAGES {
+            let actual = vmmap.page_attributes(page);
+            let expected = reference
+                .find_page(page)
+                .and_then(|entry| PageAttributes::of(entry, page, &geometry));
+            if actual != expected {
+                return fail(format!("page {page} is {actual:?}, reference {expected:?}"));
+            }
+        }
+
+        for _ in 0..8 {
+            let npages = random_npages(&mut rng, 16);
+            let page_num = rng.below(DIFFERENTIAL_PAGES - npages + 1);
+            let prot = rng.below(8) as i32;
+
+            let (actual, expected) = (
+                vmmap.check_existing_mapping(page_num, npages, prot),
+                reference.check_existing_mapping(page_num, npages, prot),
+            );
+            if actual != expected {
+                return fail(format!(
+                    "check_existing_mapping({page_num}, {npages}, {prot}) is {actual}, reference {expected}"
+                ));
+            }
+
+            let (actual, expected) = (
+                vmmap.check_addr_mapping(page_num, npages, prot).is_some(),
+                reference
+                    .check_addr_mapping(page_num, npages, prot)
+                    .is_some(),
+            );
+            if actual != expected {
+                return fail(format!(
+                    "check_addr_mapping({page_num}, {npages}, {prot}) is {actual}, reference {expected}"
+                ));
+            }
+
+            let addr = rng.below(DIFFERENTIAL_PAGES << geometry.page_shift);
+            let access =
+                [AccessKind::Read, AccessKind::Write, AccessKind::Exec][rng.below(3) as usize];
+            let (actual, expected) = (
+                vmmap.classify_fault(addr, access),
+                reference.classify_fault(addr, access),
+            );
+            if actual != expected {
+                return fail(format!(
+                    "classify_fault({addr:#x}, {access:?}) is {actual:?}, reference {expected:?}"
+                ));
+            }
+
+            let hint = rng.below(DIFFERENTIAL_PAGES);
+            let (actual, expected) = (
+                vmmap.find_map_space_with_hint(npages, hint),
+                reference.find_map_space_with_hint(npages, hint),
+            );
+            if actual != expected {
+                return fail(format!(
+                    "find_map_space_with_hint({npages}, {hint}) is {actual:?}, reference {expected:?}"
+                ));
+            }
+            if vmmap.find_space(npages) != reference.find_space(npages) {
+                return fail(format!("find_space({npages}) differs"));
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,762 @@
+use std::collections::BTreeMap;
+
+use nodit::interval::{ie, ii};
+use nodit::{Interval, NoditMap};
+
+use crate::types::VmmapEntry;
+
+/// Storage backend holding the entries of a vmmap, keyed by non-overlapping page
+/// intervals. `Vmmap` is generic over this trait, so other backends can be
+/// swapped in with `Vmmap::with_store`. Methods are named after the `NoditMap`
+/// ones they mirror. Intervals handed to a store must not be empty, and entries
+/// are iterated in ascending order. `Clone` copies every entry and `Default` is
+/// an empty store, e.g. for a forked vmmap.
+pub trait EntryStore: Clone + Default + Send {
+    /// Entry whose interval contains `point`, along with that interval
+    fn get_key_value_at_point(&self, point: u64) -> Option<(&Interval<u64>, &VmmapEntry)>;
+
+    fn get_at_point_mut(&mut self, point: u64) -> Option<&mut VmmapEntry>;
+
+    fn overlapping(
+        &self,
+        interval: Interval<u64>,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &VmmapEntry)>;
+
+    fn overlapping_mut(
+        &mut self,
+        interval: Interval<u64>,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &mut VmmapEntry)>;
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &VmmapEntry)>;
+
+    fn iter_mut(&mut self) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &mut VmmapEntry)>;
+
+    /// Inserts `entry` at `interval` unless that overlaps an existing entry, in
+    /// which case the entry is handed back
+    #[allow(clippy::result_large_err)]
+    fn insert_strict(
+        &mut self,
+        interval: Interval<u64>,
+        entry: VmmapEntry,
+    ) -> Result<(), VmmapEntry>;
+
+    /// Removes every entry overlapping `interval` as a whole, returning them in
+    /// ascending order
+    fn remove_overlapping(&mut self, interval: Interval<u64>) -> Vec<(Interval<u64>, VmmapEntry)>;
+
+    fn len(&self) -> usize;
+
+    fn get_at_point(&self, point: u64) -> Option<&VmmapEntry> {
+        self.get_key_value_at_point(point).map(|(_, entry)| entry)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn overlaps(&self, interval: Interval<u64>) -> bool {
+        self.overlapping(interval).next().is_some()
+    }
+
+    /// Whether every page of `interval` is covered by an entry
+    fn contains_interval(&self, interval: Interval<u64>) -> bool {
+        let mut next_page = interval.start();
+        for (entry_interval, _) in self.overlapping(interval) {
+            if entry_interval.start() > next_page {
+                return false;
+            }
+            match entry_interval.end().checked_add(1) {
+                Some(end_page) => next_page = end_page,
+                None => return true,
+            }
+        }
+        next_page > interval.end()
+    }
+
+    /// Free intervals inside `interval`, in ascending order
+    fn gaps_trimmed(&self, interval: Interval<u64>) -> Vec<Interval<u64>> {
+        let mut gaps = Vec::new();
+        let mut next_page = interval.start();
+        for (entry_interval, _) in self.overlapping(interval) {
+            if entry_interval.start() > next_page {
+                gaps.push(ie(next_page, entry_interval.start()));
+            }
+            match entry_interval.end().checked_add(1) {
+                Some(end_page) => next_page = end_page,
+                None => return gaps,
+            }
+        }
+        if next_page <= interval.end() {
+            gaps.push(ii(next_page, interval.end()));
+        }
+        gaps
+    }
+
+    fn first_key_value(&self) -> Option<(&Interval<u64>, &VmmapEntry)> {
+        self.iter().next()
+    }
+
+    fn last_key_value(&self) -> Option<(&Interval<u64>, &VmmapEntry)> {
+        self.iter().next_back()
+    }
+}
+
+/// Interval tree, the default backend
+impl EntryStore for NoditMap<u64, Interval<u64>, VmmapEntry> {
+    fn get_key_value_at_point(&self, point: u64) -> Option<(&Interval<u64>, &VmmapEntry)> {
+        NoditMap::get_key_value_at_point(self, point).ok()
+    }
+
+    fn get_at_point_mut(&mut self, point: u64) -> Option<&mut VmmapEntry> {
+        NoditMap::get_at_point_mut(self, point)
+    }
+
+    fn overlapping(
+        &self,
+        interval: Interval<u64>,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &VmmapEntry)> {
+        NoditMap::overlapping(self, interval)
+    }
+
+    fn overlapping_mut(
+        &mut self,
+        interval: Interval<u64>,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &mut VmmapEntry)> {
+        NoditMap::overlapping_mut(self, interval)
+    }
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &VmmapEntry)> {
+        NoditMap::iter(self)
+    }
+
+    fn iter_mut(&mut self) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &mut VmmapEntry)> {
+        NoditMap::iter_mut(self)
+    }
+
+    fn insert_strict(
+        &mut self,
+        interval: Interval<u64>,
+        entry: VmmapEntry,
+    ) -> Result<(), VmmapEntry> {
+        NoditMap::insert_strict(self, interval, entry).map_err(|err| err.value)
+    }
+
+    fn remove_overlapping(&mut self, interval: Interval<u64>) -> Vec<(Interval<u64>, VmmapEntry)> {
+        NoditMap::remove_overlapping(self, interval).collect()
+    }
+
+    fn len(&self) -> usize {
+        NoditMap::len(self)
+    }
+
+    fn overlaps(&self, interval: Interval<u64>) -> bool {
+        NoditMap::overlaps(self, interval)
+    }
+
+    fn contains_interval(&self, interval: Interval<u64>) -> bool {
+        NoditMap::contains_interval(self, interval)
+    }
+
+    fn gaps_trimmed(&self, interval: Interval<u64>) -> Vec<Interval<u64>> {
+        NoditMap::gaps_trimmed(self, interval).collect()
+    }
+}
+
+/// Entries in a `BTreeMap` keyed by their start page. Lookups find the last
+/// entry starting at or below the point, which keeps every operation a plain
+/// ordered map search.
+#[derive(Clone, Default)]
+pub struct BTreeStore {
+    map: BTreeMap<u64, (Interval<u64>, VmmapEntry)>,
+}
+
+impl BTreeStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start page of the first entry overlapping `interval`, or the start of
+    /// `interval` if none reaches into it from below
+    fn first_key(&self, interval: Interval<u64>) -> u64 {
+        match self.map.range(..=interval.start()).next_back() {
+            Some((start, (entry_interval, _))) if entry_interval.end() >= interval.start() => {
+                *start
+            }
+            _ => interval.start(),
+        }
+    }
+}
+
+impl EntryStore for BTreeStore {
+    fn get_key_value_at_point(&self, point: u64) -> Option<(&Interval<u64>, &VmmapEntry)> {
+        let (_, (interval, entry)) = self.map.range(..=point).next_back()?;
+        (interval.end() >= point).then_some((interval, entry))
+    }
+
+    fn get_at_point_mut(&mut self, point: u64) -> Option<&mut VmmapEntry> {
+        let (_, (interval, entry)) = self.map.range_mut(..=point).next_back()?;
+        (interval.end() >= point).then_some(entry)
+    }
+
+    fn overlapping(
+        &self,
+        interval: Interval<u64>,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &VmmapEntry)> {
+        let first = self.first_key(interval);
+        self.map
+            .range(first..=interval.end())
+            .map(|(_, (interval, entry))| (interval, entry))
+    }
+
+    fn overlapping_mut(
+        &mut self,
+        interval: Interval<u64>,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &mut VmmapEntry)> {
+        let first = self.first_key(interval);
+        self.map
+            .range_mut(first..=interval.end())
+            .map(|(_, (interval, entry))| (&*interval, entry))
+    }
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &VmmapEntry)> {
+        self.map.values().map(|(interval, entry)| (interval, entry))
+    }
+
+    fn iter_mut(&mut self) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &mut VmmapEntry)> {
+        self.map
+            .values_mut()
+            .map(|(interval, entry)| (&*interval, entry))
+    }
+
+    fn insert_strict(
+        &mut self,
+        interval: Interval<u64>,
+        entry: VmmapEntry,
+    ) -> Result<(), VmmapEntry> {
+        if self.overlaps(interval) {
+            return Err(entry);
+        }
+        self.map.insert(interval.start(), (interval, entry));
+        Ok(())
+    }
+
+    fn remove_overlapping(&mut self, interval: Interval<u64>) -> Vec<(Interval<u64>, VmmapEntry)> {
+        let starts: Vec<u64> = self
+            .overlapping(interval)
+            .map(|(interval, _)| interval.start())
+            .collect();
+        starts
+            .into_iter()
+            .filter_map(|start| self.map.remove(&start))
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+/// Entry of a `PageTableStore` along with its interval
+type Slot = (Interval<u64>, VmmapEntry);
+
+/// Bits of the start page a `PageTableStore` leaf resolves
+const LEAF_BITS: u32 = 8;
+/// Bits of the start page each table above the leaves resolves
+const TABLE_BITS: u32 = 16;
+
+/// Level of a `PageTableStore`. A level covers `1 << SPAN_BITS` start pages and
+/// only holds the slots in use, sorted by start page.
+trait TableLevel: Clone + Default + Send {
+    const SPAN_BITS: u32;
+
+    fn is_empty(&self) -> bool;
+
+    /// Inserts `slot`, whose start page must not be in use yet
+    fn insert(&mut self, slot: Box<Slot>);
+
+    fn remove(&mut self, key: u64) -> Option<Box<Slot>>;
+
+    fn get_mut(&mut self, key: u64) -> Option<&mut Slot>;
+
+    /// Slot with the highest start page at or below `key`
+    fn predecessor(&self, key: u64) -> Option<&Slot>;
+
+    /// Slots starting in `[low, high]`
+    fn range(
+        &self,
+        low: u64,
+        high: u64,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &VmmapEntry)>;
+
+    fn range_mut(
+        &mut self,
+        low: u64,
+        high: u64,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &mut VmmapEntry)>;
+}
+
+/// Last level, holding the entries themselves. They are boxed so that making
+/// room for a new one only moves pointers.
+#[derive(Clone, Default)]
+struct Leaf {
+    #[allow(clippy::vec_box)]
+    slots: Vec<Box<Slot>>,
+}
+
+impl Leaf {
+    /// Index of the first slot starting after `key`
+    fn after(&self, key: u64) -> usize {
+        self.slots.partition_point(|slot| slot.0.start() <= key)
+    }
+
+    /// Indices of the slots starting in `[low, high]`
+    fn bounds(&self, low: u64, high: u64) -> std::ops::Range<usize> {
+        self.slots.partition_point(|slot| slot.0.start() < low)..self.after(high)
+    }
+}
+
+impl TableLevel for Leaf {
+    const SPAN_BITS: u32 = LEAF_BITS;
+
+    fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    fn insert(&mut self, slot: Box<Slot>) {
+        let index = self.after(slot.0.start());
+        self.slots.insert(index, slot);
+    }
+
+    fn remove(&mut self, key: u64) -> Option<Box<Slot>> {
+        let index = self
+            .slots
+            .binary_search_by_key(&key, |slot| slot.0.start())
+            .ok()?;
+        Some(self.slots.remove(index))
+    }
+
+    fn get_mut(&mut self, key: u64) -> Option<&mut Slot> {
+        let index = self
+            .slots
+            .binary_search_by_key(&key, |slot| slot.0.start())
+            .ok()?;
+        Some(&mut self.slots[index])
+    }
+
+    fn predecessor(&self, key: u64) -> Option<&Slot> {
+        let index = self.after(key).checked_sub(1)?;
+        Some(&self.slots[index])
+    }
+
+    fn range(
+        &self,
+        low: u64,
+        high: u64,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &VmmapEntry)> {
+        let bounds = self.bounds(low, high);
+        self.slots[bounds].iter().map(|slot| (&slot.0, &slot.1))
+    }
+
+    fn range_mut(
+        &mut self,
+        low: u64,
+        high: u64,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &mut VmmapEntry)> {
+        let bounds = self.bounds(low, high);
+        self.slots[bounds].iter_mut().map(|slot| {
+            let (interval, entry) = &mut **slot;
+            (&*interval, entry)
+        })
+    }
+}
+
+/// Table of lower levels, each stored with the first start page it covers
+#[derive(Clone)]
+struct Table<L> {
+    children: Vec<(u64, L)>,
+}
+
+impl<L> Default for Table<L> {
+    fn default() -> Self {
+        Table {
+            children: Vec::new(),
+        }
+    }
+}
+
+impl<L: TableLevel> Table<L> {
+    /// First start page of the child covering `key`
+    fn prefix(key: u64) -> u64 {
+        key & !((1 << L::SPAN_BITS) - 1)
+    }
+
+    fn child(&self, key: u64) -> Result<usize, usize> {
+        let prefix = Self::prefix(key);
+        self.children
+            .binary_search_by_key(&prefix, |(prefix, _)| *prefix)
+    }
+
+    /// Indices of the children covering start pages in `[low, high]`
+    fn bounds(&self, low: u64, high: u64) -> std::ops::Range<usize> {
+        let first = self
+            .children
+            .partition_point(|(prefix, _)| *prefix < Self::prefix(low));
+        first..self.children.partition_point(|(prefix, _)| *prefix <= high)
+    }
+}
+
+impl<L: TableLevel> TableLevel for Table<L> {
+    const SPAN_BITS: u32 = L::SPAN_BITS + TABLE_BITS;
+
+    fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    fn insert(&mut self, slot: Box<Slot>) {
+        let key = slot.0.start();
+        let index = self.child(key).unwrap_or_else(|index| {
+            self.children
+                .insert(index, (Self::prefix(key), L::default()));
+            index
+        });
+        self.children[index].1.insert(slot);
+    }
+
+    /// Removes the slot at `key`, dropping the child it leaves empty
+    fn remove(&mut self, key: u64) -> Option<Box<Slot>> {
+        let index = self.child(key).ok()?;
+        let removed = self.children[index].1.remove(key);
+        if self.children[index].1.is_empty() {
+            self.children.remove(index);
+        }
+        removed
+    }
+
+    fn get_mut(&mut self, key: u64) -> Option<&mut Slot> {
+        let index = self.child(key).ok()?;
+        self.children[index].1.get_mut(key)
+    }
+
+    fn predecessor(&self, key: u64) -> Option<&Slot> {
+        let end = self.children.partition_point(|(prefix, _)| *prefix <= key);
+        self.children[..end]
+            .iter()
+            .rev()
+            .find_map(|(_, child)| child.predecessor(key))
+    }
+
+    fn range(
+        &self,
+        low: u64,
+        high: u64,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &VmmapEntry)> {
+        let bounds = self.bounds(low, high);
+        self.children[bounds]
+            .iter()
+            .flat_map(move |(_, child)| child.range(low, high))
+    }
+
+    fn range_mut(
+        &mut self,
+        low: u64,
+        high: u64,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &mut VmmapEntry)> {
+        let bounds = self.bounds(low, high);
+        self.children[bounds]
+            .iter_mut()
+            .flat_map(move |(_, child)| child.range_mut(low, high))
+    }
+}
+
+/// Leaves cover 256 start pages and the four tables above them resolve the rest
+/// of the 64 bit page number
+type PageTable = Table<Table<Table<Table<Leaf>>>>;
+
+/// Entries in a radix tree indexed by their start page, the way a page table
+/// indexes addresses: leaves resolve the low 8 bits of the page number and each
+/// table above them 16 more. Like a sparse page table, every level only holds
+/// the slots in use, so many small mappings close together share their tables
+/// and leaves.
+#[derive(Clone, Default)]
+pub struct PageTableStore {
+    table: PageTable,
+    len: usize,
+}
+
+impl PageTableStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// See `BTreeStore::first_key`
+    fn first_key(&self, interval: Interval<u64>) -> u64 {
+        match self.table.predecessor(interval.start()) {
+            Some((entry_interval, _)) if entry_interval.end() >= interval.start() => {
+                entry_interval.start()
+            }
+            _ => interval.start(),
+        }
+    }
+}
+
+impl EntryStore for PageTableStore {
+    fn get_key_value_at_point(&self, point: u64) -> Option<(&Interval<u64>, &VmmapEntry)> {
+        let (interval, entry) = self.table.predecessor(point)?;
+        (interval.end() >= point).then_some((interval, entry))
+    }
+
+    fn get_at_point_mut(&mut self, point: u64) -> Option<&mut VmmapEntry> {
+        let (interval, _) = self.get_key_value_at_point(point)?;
+        let start = interval.start();
+        self.table.get_mut(start).map(|(_, entry)| entry)
+    }
+
+    fn overlapping(
+        &self,
+        interval: Interval<u64>,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &VmmapEntry)> {
+        let first = self.first_key(interval);
+        self.table.range(first, interval.end())
+    }
+
+    fn overlapping_mut(
+        &mut self,
+        interval: Interval<u64>,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &mut VmmapEntry)> {
+        let first = self.first_key(interval);
+        self.table.range_mut(first, interval.end())
+    }
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &VmmapEntry)> {
+        self.table.range(0, u64::MAX)
+    }
+
+    fn iter_mut(&mut self) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &mut VmmapEntry)> {
+        self.table.range_mut(0, u64::MAX)
+    }
+
+    fn insert_strict(
+        &mut self,
+        interval: Interval<u64>,
+        entry: VmmapEntry,
+    ) -> Result<(), VmmapEntry> {
+        if self.overlaps(interval) {
+            return Err(entry);
+        }
+        self.table.insert(Box::new((interval, entry)));
+        self.len += 1;
+        Ok(())
+    }
+
+    fn remove_overlapping(&mut self, interval: Interval<u64>) -> Vec<(Interval<u64>, VmmapEntry)> {
+        let starts: Vec<u64> = self
+            .overlapping(interval)
+            .map(|(interval, _)| interval.start())
+            .collect();
+        let removed: Vec<_> = starts
+            .into_iter()
+            .filter_map(|start| self.table.remove(start))
+            .map(|slot| *slot)
+            .collect();
+        self.len -= removed.len();
+        removed
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nodit::interval::{ie, ii};
+    use nodit::{Interval, NoditMap};
+
+    use super::{BTreeStore, EntryStore, PageTableStore};
+    use crate::constants::{PROT_READ, PROT_WRITE};
+    use crate::types::{MemoryBackingType, VmmapEntry, VmmapGeometry, VmmapOps};
+    use crate::utils::SeededRng;
+    use crate::vmmap::Vmmap;
+    use crate::vmmap_entries::test_vmmap_entry_util::create_default_vmmap_entry;
+
+    fn entry(interval: Interval<u64>) -> VmmapEntry {
+        let mut entry = create_default_vmmap_entry();
+        entry.page_num = interval.start();
+        entry.npages = interval.end() + 1 - interval.start();
+        entry
+    }
+
+    /// Runs random inserts, removals and queries against a store made by `make`
+    /// and a sorted list of entries, which every backend has to agree with
+    fn check_store<S: EntryStore>(make: impl Fn() -> S) {
+        for seed in 0..8 {
+            let mut rng = SeededRng::new(seed);
+            let mut store = make();
+            let mut model: Vec<(Interval<u64>, VmmapEntry)> = Vec::new();
+            // spread over several radix levels, with the last page at the very top
+            let base = [0, 1 << 20, u64::MAX - 4095][seed as usize % 3];
+
+            for step in 0..400 {
+                let start = base + rng.below(4000);
+                let interval = ii(start, start.saturating_add(rng.below(40)));
+                let overlapping: Vec<_> = model
+                    .iter()
+                    .filter(|(key, _)| {
+                        key.start() <= interval.end() && key.end() >= interval.start()
+                    })
+                    .cloned()
+                    .collect();
+
+                match rng.below(3) {
+                    0 | 1 => {
+                        let result = store.insert_strict(interval, entry(interval));
+                        assert_eq!(
+                            result.is_ok(),
+                            overlapping.is_empty(),
+                            "seed {seed} step {step}"
+                        );
+                        if result.is_ok() {
+                            model.push((interval, entry(interval)));
+                            model.sort_by_key(|(key, _)| key.start());
+                        }
+                    }
+                    _ => {
+                        assert_eq!(store.remove_overlapping(interval), overlapping);
+                        model.retain(|(key, _)| !overlapping.iter().any(|(other, _)| other == key));
+                    }
+                }
+
+                let found: Vec<_> = store.overlapping(interval).map(|(key, _)| *key).collect();
+                let expected: Vec<_> = model
+                    .iter()
+                    .filter(|(key, _)| {
+                        key.start() <= interval.end() && key.end() >= interval.start()
+                    })
+                    .map(|(key, _)| *key)
+                    .collect();
+                assert_eq!(found, expected, "seed {seed} step {step}");
+                assert_eq!(store.overlapping_mut(interval).count(), expected.len());
+
+                let point = base + rng.below(4100);
+                let at_point = model
+                    .iter()
+                    .find(|(key, _)| key.start() <= point && key.end() >= point);
+                assert_eq!(
+                    store.get_key_value_at_point(point),
+                    at_point.map(|(key, entry)| (key, entry))
+                );
+                assert_eq!(store.get_at_point_mut(point).is_some(), at_point.is_some());
+
+                let covered = (interval.start()..=interval.end()).all(|page| {
+                    model
+                        .iter()
+                        .any(|(key, _)| key.start() <= page && key.end() >= page)
+                });
+                assert_eq!(store.contains_interval(interval), covered);
+                let gap_pages: u64 = store
+                    .gaps_trimmed(interval)
+                    .iter()
+                    .map(|gap| gap.end() + 1 - gap.start())
+                    .sum();
+                let free_pages = (interval.start()..=interval.end())
+                    .filter(|page| {
+                        !model
+                            .iter()
+                            .any(|(key, _)| key.start() <= *page && key.end() >= *page)
+                    })
+                    .count();
+                assert_eq!(gap_pages, free_pages as u64);
+
+                assert_eq!(store.len(), model.len());
+                assert!(store
+                    .iter()
+                    .map(|(key, _)| *key)
+                    .eq(model.iter().map(|(key, _)| *key)));
+                assert_eq!(store.iter_mut().rev().count(), model.len());
+                assert_eq!(
+                    store.first_key_value().map(|(key, _)| *key),
+                    model.first().map(|(key, _)| *key)
+                );
+                assert_eq!(
+                    store.last_key_value().map(|(key, _)| *key),
+                    model.last().map(|(key, _)| *key)
+                );
+            }
+
+            let copy = store.clone();
+            assert!(copy.iter().eq(store.iter()));
+            assert!(S::default().is_empty());
+        }
+    }
+
+    fn apply(target: &mut impl VmmapOps, kind: u64, page_num: u64, npages: u64, prot: i32) {
+        let _ = match kind {
+            0 => target.add_entry_with_override(
+                page_num,
+                npages,
+                prot,
+                PROT_READ | PROT_WRITE,
+                0,
+                MemoryBackingType::Anonymous,
+                0,
+                0,
+                1,
+            ),
+            1 => target.remove_entry(page_num, npages),
+            _ => target.change_prot(page_num, npages, prot),
+        };
+    }
+
+    /// Runs random maps, unmaps and protection changes against a vmmap backed by
+    /// `store` and one backed by the default store, which have to stay identical
+    fn check_vmmap<S: EntryStore>(store: S) {
+        for seed in 0..8 {
+            let mut rng = SeededRng::new(seed);
+            let mut vmmap = Vmmap::with_store(store.clone(), VmmapGeometry::default(), 0, 1 << 10);
+            let mut expected = Vmmap::with_bounds(0, 1 << 10);
+
+            for step in 0..200 {
+                let npages = 1 + rng.below(32);
+                let page_num = rng.below((1 << 10) - npages);
+                let prot = [PROT_READ, PROT_READ | PROT_WRITE][rng.below(2) as usize];
+                let kind = rng.below(3);
+                apply(&mut vmmap, kind, page_num, npages, prot);
+                apply(&mut expected, kind, page_num, npages, prot);
+
+                assert!(
+                    vmmap.entries.iter().eq(EntryStore::iter(&expected.entries)),
+                    "seed {seed} step {step}"
+                );
+                assert!(vmmap.diff(&expected).is_empty());
+                vmmap.validate().unwrap();
+                assert_eq!(vmmap.find_space(npages), expected.find_space(npages));
+            }
+
+            let child = vmmap.fork(2).unwrap();
+            assert_eq!(child.entries.len(), vmmap.entries.len());
+
+            // the whole vmmap can move to another thread
+            fn assert_send<T: Send>(_: &T) {}
+            assert_send(&child);
+        }
+    }
+
+    #[test]
+    fn test_nodit_map_conformance() {
+        check_store(NoditMap::<u64, Interval<u64>, VmmapEntry>::new);
+        check_vmmap(NoditMap::<u64, Interval<u64>, VmmapEntry>::new());
+    }
+
+    #[test]
+    fn test_btree_store_conformance() {
+        check_store(BTreeStore::new);
+        check_vmmap(BTreeStore::new());
+    }
+
+    #[test]
+    fn test_page_table_store_conformance() {
+        check_store(PageTableStore::new);
+        check_vmmap(PageTableStore::new());
+        assert!(PageTableStore::new().overlapping(ie(0, 1)).next().is_none());
+    }
+}
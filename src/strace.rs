@@ -0,0 +1,1005 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead};
+
+use nodit::interval::ie;
+
+use crate::constants::{
+    EINVAL, EOVERFLOW, MAP_ANONYMOUS, MAP_FIXED, MAP_FIXED_NOREPLACE, MAP_NORESERVE, MAP_PRIVATE,
+    MAP_SHARED, MREMAP_FIXED, MREMAP_MAYMOVE, PAGESHIFT, PROT_EXEC, PROT_MASK, PROT_NONE,
+    PROT_READ, PROT_WRITE,
+};
+use crate::placement::TopDown;
+use crate::types::{MemoryBackingType, VmmapEntry, VmmapGeometry, VmmapOp, VmmapOps};
+use crate::vmmap::Vmmap;
+
+/// Number of pages of a 47 bit user address space
+const USER_PAGES: u64 = 1 << (47 - PAGESHIFT);
+
+/// Size of the units of the mmap2 offset argument
+const MMAP2_UNIT: i64 = 4096;
+
+const PROT_NAMES: &[(&str, i32)] = &[
+    ("PROT_NONE", PROT_NONE),
+    ("PROT_READ", PROT_READ),
+    ("PROT_WRITE", PROT_WRITE),
+    ("PROT_EXEC", PROT_EXEC),
+];
+
+const MAP_NAMES: &[(&str, u32)] = &[
+    ("MAP_SHARED", MAP_SHARED),
+    ("MAP_PRIVATE", MAP_PRIVATE),
+    ("MAP_FIXED", MAP_FIXED),
+    ("MAP_ANONYMOUS", MAP_ANONYMOUS),
+    ("MAP_ANON", MAP_ANONYMOUS),
+    ("MAP_NORESERVE", MAP_NORESERVE),
+    ("MAP_FIXED_NOREPLACE", MAP_FIXED_NOREPLACE),
+];
+
+const MREMAP_NAMES: &[(&str, u32)] = &[
+    ("MREMAP_MAYMOVE", MREMAP_MAYMOVE),
+    ("MREMAP_FIXED", MREMAP_FIXED),
+];
+
+/// Address space related call found in an `strace -f` log
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TracedCall {
+    Mmap {
+        addr: u64,
+        len: u64,
+        prot: i32,
+        flags: i32,
+        fd: i64,
+        offset: i64, // in bytes, also for mmap2
+    },
+    Munmap {
+        addr: u64,
+        len: u64,
+    },
+    Mprotect {
+        addr: u64,
+        len: u64,
+        prot: i32,
+    },
+    Mremap {
+        old_addr: u64,
+        old_len: u64,
+        new_len: u64,
+        flags: i32,
+        new_addr: u64, // only meaningful with MREMAP_FIXED
+    },
+    Brk {
+        addr: u64,
+    },
+    /// clone, clone3, fork or vfork, returning the child pid
+    Clone {
+        shares_vm: bool, // CLONE_VM, the child runs in the address space of its parent
+    },
+    Execve,
+}
+
+/// Completed call of a traced process
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TracedSyscall {
+    pub pid: u64, // 0 if the log does not name the process
+    pub call: TracedCall,
+    pub result: Option<u64>, // None if the call failed
+    pub text: String,        // the call as written in the log, without its result
+}
+
+/// Turns the lines of an `strace -f` log into `TracedSyscall`s. Handles pid and
+/// timestamp prefixes, and calls interrupted by another process, which strace
+/// splits into an `<unfinished ...>` and a `<... resumed>` line.
+#[derive(Default)]
+pub struct StraceParser {
+    unfinished: HashMap<u64, String>, // start of the interrupted call, by pid
+}
+
+impl StraceParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses one line of the log. Returns None for lines that do not complete
+    /// one of the calls of `TracedCall`, such as other calls, signals, exits, or
+    /// the first half of an interrupted call.
+    pub fn parse_line(&mut self, line: &str) -> Option<TracedSyscall> {
+        let (pid, rest) = split_pid(line.trim());
+        let rest = skip_timestamps(rest);
+
+        if let Some(head) = rest.strip_suffix("<unfinished ...>") {
+            self.unfinished.insert(pid, head.trim_end().to_string());
+            return None;
+        }
+        if let Some(resumed) = rest.strip_prefix("<... ") {
+            let (_, tail) = resumed.split_once(" resumed>")?;
+            let head = self.unfinished.remove(&pid)?;
+            return parse_call(pid, &format!("{head}{tail}"));
+        }
+        parse_call(pid, rest)
+    }
+
+    /// Processes with an interrupted clone, fork or vfork, and whether the child
+    /// shares their address space. Such a child can show up in the log before
+    /// the call returns its pid.
+    pub fn pending_clones(&self) -> impl Iterator<Item = (u64, bool)> + '_ {
+        self.unfinished.iter().filter_map(|(pid, head)| {
+            let (name, args) = head.split_once('(')?;
+            is_clone(name).then(|| (*pid, shares_vm(name, args)))
+        })
+    }
+}
+
+/// Splits off the `[pid N]` or `N` prefix strace -f puts in front of the calls
+fn split_pid(line: &str) -> (u64, &str) {
+    if let Some((pid, rest)) = line
+        .strip_prefix("[pid")
+        .and_then(|rest| rest.split_once(']'))
+    {
+        return (pid.trim().parse().unwrap_or(0), rest.trim_start());
+    }
+    match line.split_once(char::is_whitespace) {
+        Some((pid, rest)) if !pid.is_empty() && pid.bytes().all(|b| b.is_ascii_digit()) => {
+            (pid.parse().unwrap_or(0), rest.trim_start())
+        }
+        _ => (0, line),
+    }
+}
+
+/// Skips the timestamps printed by strace -t, -tt or -ttt
+fn skip_timestamps(mut rest: &str) -> &str {
+    while let Some((token, tail)) = rest.split_once(' ') {
+        if token.is_empty()
+            || !token
+                .bytes()
+                .all(|b| b.is_ascii_digit() || b == b':' || b == b'.')
+        {
+            break;
+        }
+        rest = tail.trim_start();
+    }
+    rest
+}
+
+fn is_clone(name: &str) -> bool {
+    matches!(name, "clone" | "clone3" | "fork" | "vfork")
+}
+
+fn shares_vm(name: &str, args: &str) -> bool {
+    name == "vfork"
+        || args
+            .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .any(|token| token == "CLONE_VM")
+}
+
+/// Parses `name(args) = result`
+fn parse_call(pid: u64, text: &str) -> Option<TracedSyscall> {
+    let (name, rest) = text.split_once('(')?;
+    let (args_text, result) = rest.rsplit_once(") = ")?;
+    let args = split_args(args_text);
+    let arg = |index: usize| args.get(index).copied();
+
+    let call = match name {
+        "mmap" | "mmap2" => {
+            let offset = signed(arg(5)?)?;
+            TracedCall::Mmap {
+                addr: number(arg(0)?)?,
+                len: number(arg(1)?)?,
+                prot: bits(arg(2)?, PROT_NAMES),
+                flags: bits(arg(3)?, MAP_NAMES),
+                fd: signed(arg(4)?)?,
+                offset: if name == "mmap2" {
+                    offset.checked_mul(MMAP2_UNIT)?
+                } else {
+                    offset
+                },
+            }
+        }
+        "munmap" => TracedCall::Munmap {
+            addr: number(arg(0)?)?,
+            len: number(arg(1)?)?,
+        },
+        "mprotect" | "pkey_mprotect" => TracedCall::Mprotect {
+            addr: number(arg(0)?)?,
+            len: number(arg(1)?)?,
+            prot: bits(arg(2)?, PROT_NAMES),
+        },
+        "mremap" => TracedCall::Mremap {
+            old_addr: number(arg(0)?)?,
+            old_len: number(arg(1)?)?,
+            new_len: number(arg(2)?)?,
+            flags: bits(arg(3)?, MREMAP_NAMES),
+            new_addr: arg(4).and_then(number).unwrap_or(0),
+        },
+        "brk" => TracedCall::Brk {
+            addr: number(arg(0)?)?,
+        },
+        "execve" | "execveat" => TracedCall::Execve,
+        _ if is_clone(name) => TracedCall::Clone {
+            shares_vm: shares_vm(name, args_text),
+        },
+        _ => return None,
+    };
+
+    // failed calls return -1 followed by the errno name, a process gone during
+    // the call returns ?
+    let result = result.split_whitespace().next()?;
+    let result = if result.starts_with('-') || result == "?" {
+        None
+    } else {
+        Some(number(result)?)
+    };
+
+    Some(TracedSyscall {
+        pid,
+        call,
+        result,
+        text: format!("{name}({args_text})"),
+    })
+}
+
+/// Splits the arguments of a call at the commas outside of brackets and strings
+fn split_args(args: &str) -> Vec<&str> {
+    let mut split = Vec::new();
+    let (mut depth, mut in_string, mut escaped) = (0, false, false);
+    let mut start = 0;
+    for (index, c) in args.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                split.push(args[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    if !args[start..].trim().is_empty() {
+        split.push(args[start..].trim());
+    }
+    split
+}
+
+/// Parses NULL, a hexadecimal or a decimal number
+fn number(value: &str) -> Option<u64> {
+    match value {
+        "NULL" => Some(0),
+        _ => match value.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => value.parse().ok(),
+        },
+    }
+}
+
+fn signed(value: &str) -> Option<i64> {
+    match value.strip_prefix('-') {
+        Some(value) => number(value).and_then(|value| i64::try_from(value).ok().map(|v| -v)),
+        None => number(value).and_then(|value| i64::try_from(value).ok()),
+    }
+}
+
+/// Parses flags such as `PROT_READ|PROT_WRITE`. Names missing from `names` are
+/// left out, numbers are taken as they are.
+fn bits<T: Copy + TryInto<i32>>(value: &str, names: &[(&str, T)]) -> i32 {
+    value
+        .split('|')
+        .filter_map(
+            |token| match names.iter().find(|(name, _)| *name == token) {
+                Some((_, bits)) => (*bits).try_into().ok(),
+                None => number(token).and_then(|bits| i32::try_from(bits).ok()),
+            },
+        )
+        .fold(0, |all, bits| all | bits)
+}
+
+/// Address space shared by one or more traced processes
+pub struct AddressSpace {
+    pub pids: Vec<u64>, // processes running in it, in the order they were seen
+    pub vmmap: Vmmap,
+    brk_start: Option<u64>, // initial program break, learnt from the first brk
+    brk: Option<u64>,       // current program break
+    mmap_base: Option<u64>, // page top down placement starts below, see `StraceReplay`
+}
+
+/// What the vmmap says a call should have returned: None if it has no opinion,
+/// Some(None) if the call should have failed
+type Prediction = Option<Option<u64>>;
+
+/// Prediction for a call along with the outcome of applying it as traced
+type Replayed = (Prediction, Result<(), io::Error>);
+
+impl AddressSpace {
+    fn new(pid: u64) -> Self {
+        let geometry = VmmapGeometry::new(PAGESHIFT, PAGESHIFT);
+        AddressSpace {
+            pids: vec![pid],
+            vmmap: Vmmap::with_geometry(geometry, 0, USER_PAGES),
+            brk_start: None,
+            brk: None,
+            mmap_base: None,
+        }
+    }
+
+    /// Program break range `[start, end)`, once a brk call was seen
+    pub fn heap(&self) -> Option<(u64, u64)> {
+        Some((self.brk_start?, self.brk?))
+    }
+
+    /// Mappings in the style of `/proc/<pid>/maps`, one line per entry
+    pub fn maps(&self) -> Vec<String> {
+        let geometry = self.vmmap.geometry;
+        let heap = self
+            .heap()
+            .map(|(start, end)| (geometry.addr_to_page(start), geometry.bytes_to_pages(end)));
+        self.vmmap
+            .entries
+            .iter()
+            .map(|(interval, entry)| {
+                let (start, end) = (interval.start(), interval.end() + 1);
+                let perms: String = [(PROT_READ, 'r'), (PROT_WRITE, 'w'), (PROT_EXEC, 'x')]
+                    .iter()
+                    .map(|(bit, c)| if entry.prot & bit != 0 { *c } else { '-' })
+                    .chain([if entry.flags & MAP_SHARED as i32 != 0 {
+                        's'
+                    } else {
+                        'p'
+                    }])
+                    .collect();
+                let name = match entry.backing {
+                    MemoryBackingType::FileDescriptor(fd) => format!("fd {fd}"),
+                    MemoryBackingType::SharedMemory(shmid) => format!("shm {shmid}"),
+                    MemoryBackingType::Anonymous
+                        if heap.is_some_and(|(heap_start, heap_end)| {
+                            heap_start <= start && end <= heap_end
+                        }) =>
+                    {
+                        "[heap]".to_string()
+                    }
+                    MemoryBackingType::Anonymous | MemoryBackingType::None => String::new(),
+                };
+                format!(
+                    "{:012x}-{:012x} {perms} {:08x} {name}",
+                    start << geometry.page_shift,
+                    end << geometry.page_shift,
+                    entry.file_offset
+                )
+                .trim_end()
+                .to_string()
+            })
+            .collect()
+    }
+
+    /// Where a non-fixed mapping of `npages` pages would go: at the hint if it is
+    /// free, top down from `mmap_base` otherwise
+    fn place(&self, hint: u64, npages: u64) -> Prediction {
+        self.mmap_base?;
+        let geometry = self.vmmap.geometry;
+        let hint_page = geometry.addr_to_page(hint);
+        let hint_free = hint_page
+            .checked_add(npages)
+            .is_some_and(|end| end <= self.vmmap.end_page)
+            && !self
+                .vmmap
+                .entries
+                .overlaps(ie(hint_page, hint_page + npages));
+        if hint != 0 && hint_free {
+            return Some(geometry.page_to_addr(hint_page));
+        }
+        let interval = self.vmmap.find_map_space(npages);
+        Some(interval.and_then(|interval| geometry.page_to_addr(interval.start())))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn mmap(
+        &mut self,
+        pid: u64,
+        addr: u64,
+        len: u64,
+        prot: i32,
+        flags: i32,
+        fd: i64,
+        offset: i64,
+        result: Option<u64>,
+    ) -> Replayed {
+        let geometry = self.vmmap.geometry;
+        let page_num = geometry.addr_to_page(addr);
+        let npages = geometry.bytes_to_pages(len);
+        let fixed = flags & (MAP_FIXED | MAP_FIXED_NOREPLACE) as i32 != 0;
+        let prediction = if npages == 0
+            || flags & MAP_FIXED_NOREPLACE as i32 != 0
+                && self
+                    .vmmap
+                    .entries
+                    .overlaps(ie(page_num, page_num.saturating_add(npages)))
+        {
+            Some(None)
+        } else if fixed {
+            Some(Some(addr))
+        } else {
+            self.place(addr, npages)
+        };
+
+        let Some(addr) = result else {
+            return (prediction, Ok(()));
+        };
+        let page_num = geometry.addr_to_page(addr);
+        if !fixed && self.mmap_base.is_none() {
+            // the kernel puts the first mapping right below mmap_base
+            let mmap_base = page_num + npages;
+            self.mmap_base = Some(mmap_base);
+            self.vmmap.set_placement(TopDown::new(mmap_base));
+        }
+        let (backing, offset) = if flags & MAP_ANONYMOUS as i32 != 0 {
+            (MemoryBackingType::Anonymous, 0)
+        } else {
+            (MemoryBackingType::FileDescriptor(fd as u64), offset)
+        };
+        let mapped = self.vmmap.add_entry_with_override(
+            page_num,
+            npages,
+            prot & PROT_MASK as i32,
+            PROT_MASK as i32,
+            flags,
+            backing,
+            offset,
+            i64::MAX,
+            pid,
+        );
+        (prediction, mapped)
+    }
+
+    fn munmap(&mut self, addr: u64, len: u64, result: Option<u64>) -> Prediction {
+        result?;
+        let geometry = self.vmmap.geometry;
+        let unmapped = self
+            .vmmap
+            .remove_entry(geometry.addr_to_page(addr), geometry.bytes_to_pages(len));
+        unmapped.is_err().then_some(None)
+    }
+
+    fn mprotect(&mut self, addr: u64, len: u64, prot: i32, result: Option<u64>) -> Prediction {
+        result?;
+        let geometry = self.vmmap.geometry;
+        let changed = self.vmmap.change_prot(
+            geometry.addr_to_page(addr),
+            geometry.bytes_to_pages(len),
+            prot & PROT_MASK as i32,
+        );
+        changed.is_err().then_some(None)
+    }
+
+    /// Grows or shrinks in place when the pages after the mapping allow it, moves
+    /// the mapping otherwise. Every entry of the old range keeps its attributes at
+    /// its new place, grown pages continue the last one.
+    fn mremap(
+        &mut self,
+        old_addr: u64,
+        old_len: u64,
+        new_len: u64,
+        flags: i32,
+        new_addr: u64,
+        result: Option<u64>,
+    ) -> Replayed {
+        let geometry = self.vmmap.geometry;
+        let old_page = geometry.addr_to_page(old_addr);
+        let old_npages = geometry.bytes_to_pages(old_len);
+        let new_npages = geometry.bytes_to_pages(new_len);
+        let old_end = old_page.saturating_add(old_npages);
+
+        let in_place = new_npages <= old_npages
+            || !self
+                .vmmap
+                .entries
+                .overlaps(ie(old_end, old_page.saturating_add(new_npages)));
+        let source = self.vmmap.find_page(old_page);
+        let prediction = if source.is_none() || new_npages == 0 {
+            Some(None)
+        } else if old_npages == 0
+            && (flags & MREMAP_MAYMOVE as i32 == 0
+                || source.is_some_and(|entry| entry.flags & MAP_SHARED as i32 == 0))
+        {
+            // only shared mappings can be duplicated, and only to a new address
+            Some(None)
+        } else if flags & MREMAP_FIXED as i32 != 0 {
+            Some(Some(new_addr))
+        } else if in_place {
+            Some(Some(old_addr))
+        } else if flags & MREMAP_MAYMOVE as i32 != 0 {
+            self.place(0, new_npages)
+        } else {
+            Some(None)
+        };
+
+        let Some(addr) = result else {
+            return (prediction, Ok(()));
+        };
+        let new_page = geometry.addr_to_page(addr);
+        // `npages` of `entry` starting at `from_page`, mapped again at `to_page`
+        let moved = |entry: &VmmapEntry, from_page: u64, to_page: u64, npages: u64| {
+            let file_offset = match entry.backing {
+                MemoryBackingType::FileDescriptor(_) | MemoryBackingType::SharedMemory(_) => {
+                    geometry
+                        .pages_to_bytes(from_page - entry.page_num)
+                        .and_then(|bytes| i64::try_from(bytes).ok())
+                        .and_then(|skipped| entry.file_offset.checked_add(skipped))
+                        .ok_or_else(|| io::Error::from_raw_os_error(EOVERFLOW))?
+                }
+                MemoryBackingType::Anonymous | MemoryBackingType::None => 0,
+            };
+            Ok(VmmapOp::Map {
+                page_num: to_page,
+                npages,
+                prot: entry.prot,
+                maxprot: entry.maxprot,
+                flags: entry.flags,
+                backing: entry.backing,
+                file_offset,
+                file_size: entry.file_size,
+                cage_id: entry.cage_id,
+            })
+        };
+
+        // a zero old length maps the source again and keeps it
+        if old_npages == 0 {
+            let Some(entry) = self.vmmap.find_page(old_page) else {
+                return (prediction, Err(io::Error::from_raw_os_error(EINVAL)));
+            };
+            let duplicated = moved(entry, old_page, new_page, new_npages)
+                .and_then(|map| self.vmmap.apply_op(&map));
+            return (prediction, duplicated);
+        }
+
+        // pieces of the old range that are kept, as (entry, from_page, npages)
+        let mut pieces: Vec<(&VmmapEntry, u64, u64)> = Vec::new();
+        for (interval, entry) in self.vmmap.entries.overlapping(ie(old_page, old_end)) {
+            let start = interval.start().max(old_page);
+            let end = (interval.end() + 1)
+                .min(old_end)
+                .min(old_page.saturating_add(new_npages));
+            if start < end {
+                pieces.push((entry, start, end - start));
+            }
+        }
+        if new_npages > old_npages {
+            let grown = new_npages - old_npages;
+            match pieces.last_mut() {
+                Some((_, from_page, npages)) if *from_page + *npages == old_end => *npages += grown,
+                _ => {
+                    if let Some((_, entry)) =
+                        self.vmmap.entries.overlapping(ie(old_page, old_end)).last()
+                    {
+                        pieces.push((entry, old_end, grown));
+                    }
+                }
+            }
+        }
+        let maps: Result<Vec<VmmapOp>, io::Error> = pieces
+            .into_iter()
+            .map(|(entry, from_page, npages)| {
+                moved(entry, from_page, new_page + (from_page - old_page), npages)
+            })
+            .collect();
+        let maps = match maps {
+            Ok(maps) => maps,
+            Err(err) => return (prediction, Err(err)),
+        };
+
+        let mut transaction = self.vmmap.transaction();
+        transaction.stage(VmmapOp::Unmap {
+            page_num: old_page,
+            npages: old_npages,
+        });
+        for map in maps {
+            transaction.stage(map);
+        }
+        (prediction, transaction.commit())
+    }
+
+    /// Moves the program break to `addr` if the pages up to it are free. Like the
+    /// syscall it never fails, it returns the unchanged break instead.
+    fn brk(&mut self, pid: u64, addr: u64, result: Option<u64>) -> Replayed {
+        let Some(new_brk) = result else {
+            return (None, Ok(()));
+        };
+        let (Some(start), Some(current)) = (self.brk_start, self.brk) else {
+            self.brk_start = Some(new_brk);
+            self.brk = Some(new_brk);
+            return (None, Ok(()));
+        };
+
+        let geometry = self.vmmap.geometry;
+        let current_end = geometry.bytes_to_pages(current);
+        let grown_free = |end_page: u64| {
+            end_page <= current_end || !self.vmmap.entries.overlaps(ie(current_end, end_page))
+        };
+        let predicted = if addr < start || !grown_free(geometry.bytes_to_pages(addr)) {
+            current
+        } else {
+            addr
+        };
+
+        let new_end = geometry.bytes_to_pages(new_brk);
+        let moved = if new_end > current_end {
+            self.vmmap.add_entry_with_override(
+                current_end,
+                new_end - current_end,
+                PROT_READ | PROT_WRITE,
+                PROT_MASK as i32,
+                (MAP_PRIVATE | MAP_ANONYMOUS) as i32,
+                MemoryBackingType::Anonymous,
+                0,
+                0,
+                pid,
+            )
+        } else if new_end < current_end {
+            self.vmmap.remove_entry(new_end, current_end - new_end)
+        } else {
+            Ok(())
+        };
+        self.brk = Some(new_brk);
+        (Some(Some(predicted)), moved)
+    }
+}
+
+/// Traced call whose return value differs from what the vmmap predicted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub line: usize, // starting at 1
+    pub pid: u64,
+    pub call: String,
+    pub traced: Option<u64>,    // None if the call failed
+    pub predicted: Option<u64>, // None if the vmmap would have failed it
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let describe = |value: Option<u64>| match value {
+            Some(value) => format!("{value:#x}"),
+            None => "an error".to_string(),
+        };
+        write!(
+            f,
+            "line {}, pid {}: {} returned {}, the vmmap predicted {}",
+            self.line,
+            self.pid,
+            self.call,
+            describe(self.traced),
+            describe(self.predicted)
+        )
+    }
+}
+
+/// Traced call that succeeded but could not be applied to the vmmap, leaving it
+/// out of step with the traced process. munmap and mprotect are predicted by
+/// applying them, so their failures show up as mismatches instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApplyFailure {
+    pub line: usize, // starting at 1
+    pub pid: u64,
+    pub call: String,
+    pub error: String,
+}
+
+impl fmt::Display for ApplyFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, pid {}: {} could not be applied: {}",
+            self.line, self.pid, self.call, self.error
+        )
+    }
+}
+
+/// Drives vmmaps with the mmap, munmap, mprotect, mremap and brk calls of an
+/// `strace -f` log, one per address space: fork and clone without CLONE_VM copy
+/// the vmmap of the parent, execve starts a new one.
+///
+/// Every call is applied as traced, so that later calls see the same layout as
+/// the traced process, and its return value is compared with what the vmmap
+/// would have returned. Non-fixed mappings are predicted with top down
+/// placement from mmap_base, which is taken to be the end of the first
+/// non-fixed mapping of the address space since that is where the kernel puts
+/// it. Address space randomization and limits are not modelled.
+#[derive(Default)]
+pub struct StraceReplay {
+    parser: StraceParser,
+    spaces: Vec<AddressSpace>,
+    pids: HashMap<u64, usize>, // index into `spaces` of every process seen
+    mismatches: Vec<Mismatch>,
+    failures: Vec<ApplyFailure>,
+    line: usize,
+}
+
+impl StraceReplay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replays every line of `log`
+    pub fn replay(&mut self, log: impl BufRead) -> Result<(), io::Error> {
+        for line in log.lines() {
+            self.replay_line(&line?);
+        }
+        Ok(())
+    }
+
+    /// Replays the next line of the log
+    pub fn replay_line(&mut self, line: &str) {
+        self.line += 1;
+        let Some(syscall) = self.parser.parse_line(line) else {
+            return;
+        };
+        let index = self.space_of(syscall.pid);
+        let space = &mut self.spaces[index];
+        let pid = syscall.pid;
+
+        let (prediction, applied) = match syscall.call {
+            TracedCall::Mmap {
+                addr,
+                len,
+                prot,
+                flags,
+                fd,
+                offset,
+            } => space.mmap(pid, addr, len, prot, flags, fd, offset, syscall.result),
+            TracedCall::Munmap { addr, len } => (space.munmap(addr, len, syscall.result), Ok(())),
+            TracedCall::Mprotect { addr, len, prot } => {
+                (space.mprotect(addr, len, prot, syscall.result), Ok(()))
+            }
+            TracedCall::Mremap {
+                old_addr,
+                old_len,
+                new_len,
+                flags,
+                new_addr,
+            } => space.mremap(old_addr, old_len, new_len, flags, new_addr, syscall.result),
+            TracedCall::Brk { addr } => space.brk(pid, addr, syscall.result),
+            TracedCall::Clone { shares_vm } => {
+                // the child may already be known from its own calls
+                if let Some(child) = syscall
+                    .result
+                    .filter(|child| !self.pids.contains_key(child))
+                {
+                    self.add_child(index, child, shares_vm);
+                }
+                (None, Ok(()))
+            }
+            TracedCall::Execve => {
+                if syscall.result.is_some() {
+                    self.spaces[index].pids.retain(|other| *other != pid);
+                    self.pids.insert(pid, self.spaces.len());
+                    self.spaces.push(AddressSpace::new(pid));
+                }
+                (None, Ok(()))
+            }
+        };
+
+        if let Err(err) = applied {
+            self.failures.push(ApplyFailure {
+                line: self.line,
+                pid,
+                call: syscall.text.clone(),
+                error: err.to_string(),
+            });
+        }
+
+        if let Some(predicted) = prediction.filter(|predicted| *predicted != syscall.result) {
+            self.mismatches.push(Mismatch {
+                line: self.line,
+                pid,
+                call: syscall.text,
+                traced: syscall.result,
+                predicted,
+            });
+        }
+    }
+
+    /// Address spaces of the processes seen so far, leaving out the ones every
+    /// process left through execve
+    pub fn address_spaces(&self) -> impl Iterator<Item = &AddressSpace> {
+        self.spaces.iter().filter(|space| !space.pids.is_empty())
+    }
+
+    pub fn mismatches(&self) -> &[Mismatch] {
+        &self.mismatches
+    }
+
+    pub fn failures(&self) -> &[ApplyFailure] {
+        &self.failures
+    }
+
+    /// Index of the address space of `pid`, which is created on its first call
+    fn space_of(&mut self, pid: u64) -> usize {
+        if let Some(index) = self.pids.get(&pid) {
+            return *index;
+        }
+        // a child whose clone did not return yet, pick the lowest such parent
+        let parent = self
+            .parser
+            .pending_clones()
+            .filter_map(|(parent, shares_vm)| Some((parent, *self.pids.get(&parent)?, shares_vm)))
+            .min();
+        match parent {
+            Some((_, parent_index, shares_vm)) => self.add_child(parent_index, pid, shares_vm),
+            None => {
+                self.pids.insert(pid, self.spaces.len());
+                self.spaces.push(AddressSpace::new(pid));
+                self.spaces.len() - 1
+            }
+        }
+    }
+
+    fn add_child(&mut self, parent_index: usize, child: u64, shares_vm: bool) -> usize {
+        if shares_vm {
+            self.spaces[parent_index].pids.push(child);
+            self.pids.insert(child, parent_index);
+            return parent_index;
+        }
+
+        let parent = &self.spaces[parent_index];
+        // replayed address spaces have no commit registry, so forking cannot fail
+        let mut vmmap = parent
+            .vmmap
+            .fork(child)
+            .expect("fork refused without a commit registry");
+        if let Some(mmap_base) = parent.mmap_base {
+            vmmap.set_placement(TopDown::new(mmap_base));
+        }
+        let space = AddressSpace {
+            pids: vec![child],
+            vmmap,
+            brk_start: parent.brk_start,
+            brk: parent.brk,
+            mmap_base: parent.mmap_base,
+        };
+        self.pids.insert(child, self.spaces.len());
+        self.spaces.push(space);
+        self.spaces.len() - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE};
+
+    use super::{StraceParser, StraceReplay, TracedCall};
+
+    #[test]
+    fn test_parse_strace_lines() {
+        let mut parser = StraceParser::new();
+
+        let syscall = parser
+            .parse_line(
+                "1234  12:00:01.000001 mmap(NULL, 8192, PROT_READ|PROT_WRITE, \
+                 MAP_PRIVATE|MAP_ANONYMOUS, -1, 0) = 0x7f0000010000",
+            )
+            .unwrap();
+        assert_eq!(syscall.pid, 1234);
+        assert_eq!(syscall.result, Some(0x7f0000010000));
+        assert_eq!(
+            syscall.call,
+            TracedCall::Mmap {
+                addr: 0,
+                len: 8192,
+                prot: PROT_READ | PROT_WRITE,
+                flags: (MAP_PRIVATE | MAP_ANONYMOUS) as i32,
+                fd: -1,
+                offset: 0,
+            }
+        );
+
+        let syscall = parser
+            .parse_line("[pid  77] munmap(0x7f0000010000, 4096) = -1 EINVAL (Invalid argument)")
+            .unwrap();
+        assert_eq!((syscall.pid, syscall.result), (77, None));
+
+        // interrupted by another process
+        assert!(parser
+            .parse_line(
+                "10 clone(child_stack=0x7f0000200000, flags=CLONE_VM|CLONE_THREAD <unfinished ...>"
+            )
+            .is_none());
+        assert_eq!(parser.pending_clones().collect::<Vec<_>>(), [(10, true)]);
+        assert!(parser.parse_line("11 +++ exited with 0 +++").is_none());
+        let syscall = parser
+            .parse_line("10 <... clone resumed>, parent_tid=[11], tls=0x7f0000300000) = 11")
+            .unwrap();
+        assert_eq!(syscall.call, TracedCall::Clone { shares_vm: true });
+        assert_eq!(syscall.result, Some(11));
+        assert_eq!(parser.pending_clones().count(), 0);
+
+        assert!(parser
+            .parse_line("10 openat(AT_FDCWD, \"a, b\", O_RDONLY) = 3")
+            .is_none());
+    }
+
+    #[test]
+    fn test_replay_strace_log() {
+        let log = "\
+100 execve(\"/bin/true\", [\"true\"], 0x7ffd00000000 /* 3 vars */) = 0
+100 brk(NULL) = 0x555555560000
+100 brk(0x555555581000) = 0x555555581000
+100 mmap(NULL, 8192, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0) = 0x7f0000010000
+100 mmap(NULL, 12288, PROT_READ, MAP_PRIVATE, 3, 0) = 0x7f000000d000
+100 mmap(0x7f000000e000, 4096, PROT_READ|PROT_EXEC, MAP_PRIVATE|MAP_FIXED, 3, 0x1000) = 0x7f000000e000
+100 mprotect(0x7f000000d000, 4096, PROT_NONE) = 0
+100 mremap(0x7f0000010000, 8192, 16384, MREMAP_MAYMOVE) = 0x7f0000010000
+100 mmap(NULL, 4096, PROT_READ, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0) = 0x7f0000100000
+100 clone(child_stack=NULL, flags=CLONE_CHILD_CLEARTID|SIGCHLD <unfinished ...>
+101 munmap(0x7f0000100000, 4096) = 0
+100 <... clone resumed>, child_tidptr=0x7f0000000a10) = 101
+100 munmap(0x7f0000010000, 16384) = 0
+";
+        let mut replay = StraceReplay::new();
+        replay.replay(log.as_bytes()).unwrap();
+
+        let spaces: Vec<_> = replay.address_spaces().collect();
+        assert_eq!(spaces.len(), 2);
+        assert_eq!(spaces[0].pids, [100]);
+        assert_eq!(
+            spaces[0].maps(),
+            [
+                "555555560000-555555581000 rw-p 00000000 [heap]",
+                "7f000000d000-7f000000e000 ---p 00000000 fd 3",
+                "7f000000e000-7f000000f000 r-xp 00001000 fd 3",
+                "7f000000f000-7f0000010000 r--p 00002000 fd 3",
+                "7f0000100000-7f0000101000 r--p 00000000",
+            ]
+        );
+        // the child forked before the parent unmapped, and unmapped on its own
+        assert_eq!(spaces[1].pids, [101]);
+        assert_eq!(spaces[1].maps().len(), 5);
+        assert!(spaces[1].maps()[4].starts_with("7f0000010000-7f0000014000 rw-p"));
+
+        // the last anonymous mapping was expected right below the file mapping
+        let mismatches = replay.mismatches();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].line, 9);
+        assert_eq!(mismatches[0].traced, Some(0x7f0000100000));
+        assert_eq!(mismatches[0].predicted, Some(0x7f000000c000));
+    }
+
+    #[test]
+    fn test_mremap_moves_every_entry() {
+        let log = "\
+200 mmap(NULL, 16384, PROT_READ|PROT_WRITE, MAP_PRIVATE, 3, 0x2000) = 0x7f0000020000
+200 mprotect(0x7f0000022000, 8192, PROT_READ) = 0
+200 mremap(0x7f0000020000, 16384, 24576, MREMAP_MAYMOVE) = 0x7f0000040000
+200 mmap(0x7f0000060000, 4096, PROT_READ, MAP_PRIVATE|MAP_FIXED, 3, 0x10) = 0x7f0000060000
+200 mmap(0x7f0000070000, 8192, PROT_READ, MAP_SHARED|MAP_FIXED, 4, 0x3000) = 0x7f0000070000
+200 mremap(0x7f0000071000, 0, 8192, MREMAP_MAYMOVE) = 0x7f0000080000
+";
+        let mut replay = StraceReplay::new();
+        replay.replay(log.as_bytes()).unwrap();
+
+        let spaces: Vec<_> = replay.address_spaces().collect();
+        assert_eq!(
+            spaces[0].maps(),
+            [
+                "7f0000040000-7f0000042000 rw-p 00002000 fd 3",
+                "7f0000042000-7f0000046000 r--p 00004000 fd 3",
+                "7f0000070000-7f0000072000 r--s 00003000 fd 4",
+                "7f0000080000-7f0000082000 r--s 00004000 fd 4",
+            ]
+        );
+
+        // the misaligned offset is refused by the vmmap, not predicted to fail
+        assert!(replay
+            .mismatches()
+            .iter()
+            .all(|mismatch| mismatch.line != 4));
+        let failures = replay.failures();
+        assert_eq!(failures.len(), 1);
+        assert_eq!((failures[0].line, failures[0].pid), (4, 200));
+    }
+}
@@ -0,0 +1,248 @@
+use std::io;
+
+use crate::memory::SimulatedMemory;
+use crate::store::EntryStore;
+use crate::types::{VmmapEntry, VmmapOp, VmmapOps};
+use crate::vmmap::{DefaultStore, Vmmap};
+
+/// Everything the staged operations may change, kept to roll back to
+struct Snapshot<S> {
+    entries: S,
+    cached_entry: Option<VmmapEntry>,
+    memory: Option<SimulatedMemory>,
+    commit_charge: u64,
+    lock_future: bool,
+}
+
+/// Operations staged against a vmmap that take effect all together or not at
+/// all, for syscalls such as mremap or exec that need several edits. Nothing
+/// touches the vmmap until `commit`, dropping the transaction discards it.
+pub struct Transaction<'a, S: EntryStore = DefaultStore> {
+    vmmap: &'a mut Vmmap<S>,
+    ops: Vec<VmmapOp>,
+}
+
+impl<S: EntryStore> Vmmap<S> {
+    /// Starts staging a batch of operations, see `Transaction`
+    pub fn transaction(&mut self) -> Transaction<'_, S> {
+        Transaction {
+            vmmap: self,
+            ops: Vec::new(),
+        }
+    }
+
+    /// Applies a single operation through the regular `VmmapOps` entry points
+    pub fn apply_op(&mut self, op: &VmmapOp) -> Result<(), io::Error> {
+        match *op {
+            VmmapOp::Map {
+                page_num,
+                npages,
+                prot,
+                maxprot,
+                flags,
+                backing,
+                file_offset,
+                file_size,
+                cage_id,
+            } => self.add_entry_with_override(
+                page_num,
+                npages,
+                prot,
+                maxprot,
+                flags,
+                backing,
+                file_offset,
+                file_size,
+                cage_id,
+            ),
+            VmmapOp::Insert {
+                page_num,
+                npages,
+                prot,
+                maxprot,
+                flags,
+                backing,
+                file_offset,
+                file_size,
+                cage_id,
+            } => self.add_entry(VmmapEntry::new(
+                page_num,
+                npages,
+                prot,
+                maxprot,
+                flags,
+                false,
+                file_offset,
+                file_size,
+                cage_id,
+                backing,
+            )),
+            VmmapOp::Unmap { page_num, npages } => self.remove_entry(page_num, npages),
+            VmmapOp::Protect {
+                page_num,
+                npages,
+                prot,
+            } => self.change_prot(page_num, npages, prot),
+            VmmapOp::Mlock { page_num, npages } => self.mlock(page_num, npages),
+            VmmapOp::Munlock { page_num, npages } => self.munlock(page_num, npages),
+            VmmapOp::MlockAll { flags } => self.mlockall(flags),
+            VmmapOp::MunlockAll => {
+                self.munlockall();
+                Ok(())
+            }
+            VmmapOp::Madvise { addr, len, advice } => self.madvise(addr, len, advice),
+            VmmapOp::GrowUp { page_num, npages } => self.grow_up(page_num, npages),
+            VmmapOp::GrowDown { page_num, npages } => self.grow_down(page_num, npages),
+        }
+    }
+}
+
+impl<S: EntryStore> Transaction<'_, S> {
+    /// Adds `op` to the end of the batch
+    pub fn stage(&mut self, op: VmmapOp) -> &mut Self {
+        self.ops.push(op);
+        self
+    }
+
+    /// Operations staged so far, in the order they will be applied
+    pub fn ops(&self) -> &[VmmapOp] {
+        &self.ops
+    }
+
+    /// Applies the staged operations in order. If one of them fails, its error is
+    /// returned and the vmmap is rolled back to exactly the state it had before:
+    /// entries, simulated memory, commit charge and `mlockall` state. Observers and
+    /// the journal only hear about the changes once every operation succeeded.
+    /// Callbacks that already ran, such as the discard callback, are not undone.
+    pub fn commit(self) -> Result<(), io::Error> {
+        let vmmap = self.vmmap;
+        let snapshot = Snapshot {
+            entries: vmmap.entries.clone(),
+            cached_entry: vmmap.cached_entry.clone(),
+            memory: vmmap.memory.clone(),
+            commit_charge: vmmap.commit_charge,
+            lock_future: vmmap.lock_future,
+        };
+        vmmap.pending_events = Some(Vec::new());
+        let journal = vmmap.journal.take();
+
+        // replay checks the layout after every record, so digest each op's
+        // layout as it is applied rather than the final one
+        let mut digests = Vec::new();
+        let result = self.ops.iter().try_for_each(|op| {
+            vmmap.apply_op(op)?;
+            if journal.is_some() {
+                digests.push(vmmap.layout_digest());
+            }
+            Ok(())
+        });
+        let events = vmmap.pending_events.take().unwrap_or_default();
+        vmmap.journal = journal;
+
+        match result {
+            Ok(()) => {
+                for (op, digest) in self.ops.into_iter().zip(digests) {
+                    vmmap.record_with_digest(op, &Ok(()), digest);
+                }
+                if !events.is_empty() {
+                    vmmap.notify(events);
+                }
+                Ok(())
+            }
+            Err(err) => {
+                vmmap.entries = snapshot.entries;
+                vmmap.cached_entry = snapshot.cached_entry;
+                vmmap.memory = snapshot.memory;
+                vmmap.restore_charge(snapshot.commit_charge);
+                vmmap.lock_future = snapshot.lock_future;
+                vmmap.debug_validate();
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::constants::{ENOMEM, PROT_READ, PROT_WRITE};
+    use crate::types::{MemoryBackingType, VmmapOp, VmmapOps};
+    use crate::vmmap::Vmmap;
+
+    fn map_op(page_num: u64, npages: u64) -> VmmapOp {
+        VmmapOp::Map {
+            page_num,
+            npages,
+            prot: PROT_READ | PROT_WRITE,
+            maxprot: PROT_READ | PROT_WRITE,
+            flags: 0,
+            backing: MemoryBackingType::Anonymous,
+            file_offset: 0,
+            file_size: 0,
+            cage_id: 1,
+        }
+    }
+
+    #[test]
+    fn test_transaction_commits_all_ops() {
+        let mut vmmap = Vmmap::new();
+        vmmap.apply_op(&map_op(0, 10)).unwrap();
+
+        let mut transaction = vmmap.transaction();
+        transaction
+            .stage(VmmapOp::Unmap {
+                page_num: 0,
+                npages: 4,
+            })
+            .stage(map_op(20, 4))
+            .stage(VmmapOp::Protect {
+                page_num: 20,
+                npages: 2,
+                prot: PROT_READ,
+            });
+        assert_eq!(transaction.ops().len(), 3);
+        transaction.commit().unwrap();
+
+        assert!(vmmap.find_page(0).is_none());
+        assert_eq!(vmmap.find_page(20).unwrap().prot, PROT_READ);
+        assert_eq!(vmmap.find_page(22).unwrap().prot, PROT_READ | PROT_WRITE);
+    }
+
+    #[test]
+    fn test_transaction_rolls_back() {
+        let mut vmmap = Vmmap::new();
+        vmmap.enable_simulated_memory();
+        vmmap.apply_op(&map_op(0, 10)).unwrap();
+        vmmap.write_memory(0, b"kept").unwrap();
+        vmmap.address_space_limit = Some(12);
+
+        let events = Arc::new(Mutex::new(0));
+        let events_clone = events.clone();
+        vmmap.add_observer(move |_| *events_clone.lock().unwrap() += 1);
+
+        let entries = vmmap.entries.clone();
+        let charge = vmmap.commit_charge();
+        let mut transaction = vmmap.transaction();
+        transaction
+            .stage(VmmapOp::Unmap {
+                page_num: 0,
+                npages: 2,
+            })
+            .stage(VmmapOp::Protect {
+                page_num: 4,
+                npages: 2,
+                prot: PROT_READ,
+            })
+            .stage(map_op(20, 5));
+        let err = transaction.commit().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENOMEM));
+
+        assert_eq!(&vmmap.entries, &entries);
+        assert_eq!(vmmap.commit_charge(), charge);
+        assert_eq!(*events.lock().unwrap(), 0);
+        let mut buf = [0; 4];
+        vmmap.read_memory(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"kept");
+    }
+}
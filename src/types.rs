@@ -4,7 +4,6 @@ use nodit::Interval;
 
 /// Used to identify whether the vmmap entry is backed anonymously,
 /// by an fd, or by a shared memory segment
-
 #[allow(dead_code)]
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum MemoryBackingType {
@@ -14,13 +13,158 @@ pub enum MemoryBackingType {
     FileDescriptor(u64), // stores file descriptor addr
 }
 
+/// The kind of access a cage was attempting when it faulted
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Exec,
+}
+
+/// Outcome of looking a faulting address up in the vmmap, used by the
+/// runtime to pick which signal (if any) gets delivered to the cage
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FaultClass {
+    NotMapped,                // SIGSEGV with SEGV_MAPERR
+    ProtectionViolation(i32), // SIGSEGV with SEGV_ACCERR, stores the entry's prot
+    BeyondFileEnd,            // SIGBUS, file backed page lies past file_size
+    Allowed,                  // the access is valid, fault was not caused by the vmmap
+}
+
+/// Called with (page_num, npages, advice) when MADV_DONTNEED or MADV_FREE drop
+/// the contents of a range
+pub type DiscardCallback = Box<dyn FnMut(u64, u64, i32) + Send>;
+
+/// Part of a shared file mapping that msync needs written back
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FileSpan {
+    pub fd: u64,
+    pub file_offset: i64,
+    pub len: u64, // in bytes
+}
+
+/// Called by `Vmmap::msync` for every span to write back, along with the msync flags
+pub type WritebackCallback = Box<dyn FnMut(FileSpan, i32) -> Result<(), io::Error> + Send>;
+
+/// Mirrors `vm.overcommit_memory`, decides whether commit charge is refused
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum OvercommitMode {
+    #[default]
+    Heuristic, // refuse only single requests larger than the commit limit
+    Always, // never refuse
+    Never,  // refuse once the registry total would exceed the commit limit
+}
+
+/// Change made to `Vmmap::entries`, handed to every registered observer
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum VmmapEvent {
+    /// A new mapping now covers `interval`
+    Mapped {
+        interval: Interval<u64>,
+        entry: VmmapEntry,
+    },
+    /// The part `interval` of a mapping was unmapped or replaced by a new mapping
+    Unmapped {
+        interval: Interval<u64>,
+        entry: VmmapEntry,
+    },
+    /// The pages in `interval` went from `old_prot` to `entry.prot`
+    ProtectionChanged {
+        interval: Interval<u64>,
+        old_prot: i32,
+        entry: VmmapEntry,
+    },
+    /// The mapping covering `interval` was cut in two at `page_num`
+    Split {
+        interval: Interval<u64>,
+        page_num: u64,
+        entry: VmmapEntry,
+    },
+    /// Adjacent `intervals` holding the same entry were joined into `interval`
+    Merged {
+        intervals: Vec<Interval<u64>>,
+        interval: Interval<u64>,
+        entry: VmmapEntry,
+    },
+}
+
+/// Single edit of the vmmap, as staged in a `Transaction` or recorded in a journal
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum VmmapOp {
+    /// `add_entry_with_override` of a new mapping
+    Map {
+        page_num: u64,
+        npages: u64,
+        prot: i32,
+        maxprot: i32,
+        flags: i32,
+        backing: MemoryBackingType,
+        file_offset: i64,
+        file_size: i64,
+        cage_id: u64,
+    },
+    /// `add_entry` of a new mapping, which fails instead of replacing existing
+    /// ones. Page tracking, locks and advice of the entry are not kept.
+    Insert {
+        page_num: u64,
+        npages: u64,
+        prot: i32,
+        maxprot: i32,
+        flags: i32,
+        backing: MemoryBackingType,
+        file_offset: i64,
+        file_size: i64,
+        cage_id: u64,
+    },
+    /// `remove_entry`
+    Unmap { page_num: u64, npages: u64 },
+    /// `change_prot`
+    Protect {
+        page_num: u64,
+        npages: u64,
+        prot: i32,
+    },
+    /// `mlock`
+    Mlock { page_num: u64, npages: u64 },
+    /// `munlock`
+    Munlock { page_num: u64, npages: u64 },
+    /// `mlockall`
+    MlockAll { flags: i32 },
+    /// `munlockall`
+    MunlockAll,
+    /// `madvise`
+    Madvise { addr: u64, len: u64, advice: i32 },
+    /// `grow_up`
+    GrowUp { page_num: u64, npages: u64 },
+    /// `grow_down`
+    GrowDown { page_num: u64, npages: u64 },
+}
+
+/// Registered with `Vmmap::add_observer`
+pub type ObserverCallback = Box<dyn FnMut(&VmmapEvent) + Send>;
+
+/// Mirrors `struct iovec`, a user buffer handed to readv/writev/sendmsg
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IoVec {
+    pub iov_base: u64,
+    pub iov_len: u64,
+}
+
+/// Page size and mmap allocation granularity of an address space, both stored
+/// as shifts. Chosen once when the vmmap is created, see `Vmmap::with_geometry`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct VmmapGeometry {
+    pub page_shift: u32,     // log2 of the page size, 12 for 4 KiB pages
+    pub map_page_shift: u32, // log2 of the granularity mappings are aligned to
+}
+
 /// in the old native client based vmmap, we relied on the fd, shmid
 /// fields. Here we remove those fields and replace with a 'backing' field
 /// which is an enum containing info based on the type
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct VmmapEntry {
-    pub page_num: u32, /* base virtual addr >> NACL_PAGESHIFT */
-    pub npages: u32,   /* number of pages */
+    pub page_num: u64, /* base virtual addr >> NACL_PAGESHIFT */
+    pub npages: u64,   /* number of pages */
     pub prot: i32,     /* mprotect attribute */
     pub maxprot: i32,
     pub flags: i32,       /* mapping flags */
@@ -29,14 +173,43 @@ pub struct VmmapEntry {
     pub file_size: i64,   /* backing store size */
     pub cage_id: u64,
     pub backing: MemoryBackingType,
+    pub page_tracking: Option<PageTracking>, /* allocated when a page is first marked */
+    pub locked: bool,                        /* set by mlock */
+    pub advice: Advice,                      /* persistent madvise hints */
 }
 
-#[allow(dead_code)]
+/// Advice recorded on an entry by `Vmmap::madvise`. Only the hints that outlive
+/// the madvise call are kept, one-shot ones like MADV_DONTNEED are not stored.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Advice {
+    pub access_pattern: i32, // MADV_NORMAL, MADV_RANDOM or MADV_SEQUENTIAL
+    pub dont_fork: bool,     // MADV_DONTFORK, the child does not get this mapping
+    pub wipe_on_fork: bool,  // MADV_WIPEONFORK, the child gets zero filled pages
+    pub hugepage: bool,      // MADV_HUGEPAGE
+}
+
+/// Per-page state that can be recorded for an entry, see `Vmmap::mark_pages`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PageState {
+    Accessed,
+    Dirty,
+}
+
+/// Accessed and dirty bitmaps of an entry. Bit `i` describes page
+/// `entry.page_num + i`, the bitmaps are sliced and joined along with the entry
+/// when it is split or merged.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PageTracking {
+    pub accessed: Vec<u64>,
+    pub dirty: Vec<u64>,
+}
+
+#[allow(dead_code, clippy::too_many_arguments)]
 pub trait VmmapOps {
     fn update(
         &mut self,
-        page_num: u32,
-        npages: u32,
+        page_num: u64,
+        npages: u64,
         prot: i32,
         maxprot: i32,
         flags: i32,
@@ -47,12 +220,12 @@ pub trait VmmapOps {
         cage_id: u64,
     ) -> Result<(), io::Error>;
 
-    fn add_entry(&mut self, vmmap_entry_ref: VmmapEntry);
+    fn add_entry(&mut self, vmmap_entry_ref: VmmapEntry) -> Result<(), io::Error>;
 
     fn add_entry_with_override(
         &mut self,
-        page_num: u32,
-        npages: u32,
+        page_num: u64,
+        npages: u64,
         prot: i32,
         maxprot: i32,
         flags: i32,
@@ -62,48 +235,45 @@ pub trait VmmapOps {
         cage_id: u64,
     ) -> Result<(), io::Error>;
 
-    fn change_prot(&mut self, page_num: u32, npages: u32, new_prot: i32);
+    fn change_prot(&mut self, page_num: u64, npages: u64, new_prot: i32) -> Result<(), io::Error>;
+
+    fn remove_entry(&mut self, page_num: u64, npages: u64) -> Result<(), io::Error>;
 
-    fn remove_entry(&mut self, page_num: u32, npages: u32) -> Result<(), io::Error>;
+    fn check_existing_mapping(&self, page_num: u64, npages: u64, prot: i32) -> bool;
 
-    fn check_existing_mapping(&self, page_num: u32, npages: u32, prot: i32) -> bool;
+    fn check_addr_mapping(&mut self, page_num: u64, npages: u64, prot: i32) -> Option<u64>;
 
-    fn check_addr_mapping(&mut self, page_num: u32, npages: u32, prot: i32) -> Option<u32>;
+    fn classify_fault(&self, addr: u64, access: AccessKind) -> FaultClass;
 
-    fn find_page(&self, page_num: u32) -> Option<&VmmapEntry>;
+    fn find_page(&self, page_num: u64) -> Option<&VmmapEntry>;
 
-    fn find_page_mut(&mut self, page_num: u32) -> Option<&mut VmmapEntry>;
+    fn find_page_mut(&mut self, page_num: u64) -> Option<&mut VmmapEntry>;
 
     fn find_page_iter(
         &self,
-        page_num: u32,
-    ) -> impl DoubleEndedIterator<Item = (&Interval<u32>, &VmmapEntry)>;
+        page_num: u64,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &VmmapEntry)>;
 
     fn find_page_iter_mut(
         &mut self,
-        page_num: u32,
-    ) -> impl DoubleEndedIterator<Item = (&Interval<u32>, &mut VmmapEntry)>;
+        page_num: u64,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &mut VmmapEntry)>;
 
-    fn first_entry(&self) -> Option<(&Interval<u32>, &VmmapEntry)>;
+    fn first_entry(&self) -> Option<(&Interval<u64>, &VmmapEntry)>;
 
-    fn last_entry(&self) -> Option<(&Interval<u32>, &VmmapEntry)>;
+    fn last_entry(&self) -> Option<(&Interval<u64>, &VmmapEntry)>;
 
-    fn double_ended_iter(&self) -> impl DoubleEndedIterator<Item = (&Interval<u32>, &VmmapEntry)>;
+    fn double_ended_iter(&self) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &VmmapEntry)>;
 
     fn double_ended_iter_mut(
         &mut self,
-    ) -> impl DoubleEndedIterator<Item = (&Interval<u32>, &mut VmmapEntry)>;
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &mut VmmapEntry)>;
 
-    fn find_space(&self, npages: u32) -> Option<Interval<u32>>;
+    fn find_space(&self, npages: u64) -> Option<Interval<u64>>;
 
-    fn find_space_above_hint(&self, npages: u32, hint: u32) -> Option<Interval<u32>>;
+    fn find_space_above_hint(&self, npages: u64, hint: u64) -> Option<Interval<u64>>;
 
-    fn find_map_space(&self, num_pages: u32, pages_per_map: u32) -> Option<Interval<u32>>;
+    fn find_map_space(&self, num_pages: u64) -> Option<Interval<u64>>;
 
-    fn find_map_space_with_hint(
-        &self,
-        num_pages: u32,
-        pages_per_map: u32,
-        hint: u32,
-    ) -> Option<Interval<u32>>;
+    fn find_map_space_with_hint(&self, num_pages: u64, hint: u64) -> Option<Interval<u64>>;
 }
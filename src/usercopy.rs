@@ -0,0 +1,221 @@
+use std::ffi::CString;
+use std::io;
+
+use crate::constants::{EFAULT, ENAMETOOLONG};
+use crate::store::EntryStore;
+use crate::types::AccessKind;
+use crate::vmmap::Vmmap;
+
+/// Gives access to the bytes backing a cage's address space. Implementations
+/// do not check protections themselves, the copy helpers on `Vmmap` validate
+/// every range against the vmmap before touching it.
+pub trait CageMemory {
+    fn read(&self, addr: u64, buf: &mut [u8]) -> Result<(), io::Error>;
+
+    fn write(&mut self, addr: u64, data: &[u8]) -> Result<(), io::Error>;
+}
+
+/// Cage memory laid out linearly in the host's address space starting at `base`,
+/// the way a sandbox reserves its whole window up front
+pub struct BasePointerMemory {
+    base: *mut u8,
+}
+
+impl BasePointerMemory {
+    /// # Safety
+    ///
+    /// `base` must point to a host allocation covering every cage address that
+    /// the vmmap may report as mapped, for as long as this value is used.
+    pub unsafe fn new(base: *mut u8) -> Self {
+        BasePointerMemory { base }
+    }
+}
+
+impl CageMemory for BasePointerMemory {
+    fn read(&self, addr: u64, buf: &mut [u8]) -> Result<(), io::Error> {
+        // Safety: guaranteed by the contract of `BasePointerMemory::new`
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.base.add(addr as usize), buf.as_mut_ptr(), buf.len())
+        };
+        Ok(())
+    }
+
+    fn write(&mut self, addr: u64, data: &[u8]) -> Result<(), io::Error> {
+        // Safety: guaranteed by the contract of `BasePointerMemory::new`
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), self.base.add(addr as usize), data.len())
+        };
+        Ok(())
+    }
+}
+
+/// A plain byte slice indexed by cage address, handy for tests
+impl CageMemory for [u8] {
+    fn read(&self, addr: u64, buf: &mut [u8]) -> Result<(), io::Error> {
+        let src = usize::try_from(addr)
+            .ok()
+            .and_then(|start| self.get(start..start.checked_add(buf.len())?))
+            .ok_or_else(|| io::Error::from_raw_os_error(EFAULT))?;
+        buf.copy_from_slice(src);
+        Ok(())
+    }
+
+    fn write(&mut self, addr: u64, data: &[u8]) -> Result<(), io::Error> {
+        let dest = usize::try_from(addr)
+            .ok()
+            .and_then(|start| self.get_mut(start..start.checked_add(data.len())?))
+            .ok_or_else(|| io::Error::from_raw_os_error(EFAULT))?;
+        dest.copy_from_slice(data);
+        Ok(())
+    }
+}
+
+impl<S: EntryStore> Vmmap<S> {
+    /// Copies `buf.len()` bytes at cage address `addr` into `buf`. Nothing is copied
+    /// and EFAULT is returned unless the whole range is mapped readable.
+    pub fn copy_from_user(
+        &mut self,
+        memory: &(impl CageMemory + ?Sized),
+        addr: u64,
+        buf: &mut [u8],
+    ) -> Result<(), io::Error> {
+        self.validate_user_range(addr, buf.len() as u64, AccessKind::Read)?;
+        memory.read(addr, buf)
+    }
+
+    /// Copies `data` to cage address `addr`. Nothing is copied and EFAULT is
+    /// returned unless the whole range is mapped writable.
+    pub fn copy_to_user(
+        &mut self,
+        memory: &mut (impl CageMemory + ?Sized),
+        addr: u64,
+        data: &[u8],
+    ) -> Result<(), io::Error> {
+        self.validate_user_range(addr, data.len() as u64, AccessKind::Write)?;
+        memory.write(addr, data)
+    }
+
+    /// Reads a NUL terminated string such as a path argument, one page at a time so
+    /// that a string ending right before an unmapped page is still accepted. Fails
+    /// with EFAULT on unreadable memory and with ENAMETOOLONG if no NUL shows up in
+    /// the first `max_len` bytes.
+    pub fn read_user_string(
+        &mut self,
+        memory: &(impl CageMemory + ?Sized),
+        addr: u64,
+        max_len: usize,
+    ) -> Result<CString, io::Error> {
+        let page_size = self.geometry.page_size();
+        let mut bytes = Vec::new();
+
+        while bytes.len() < max_len {
+            let current_addr = addr
+                .checked_add(bytes.len() as u64)
+                .ok_or_else(|| io::Error::from_raw_os_error(EFAULT))?;
+            let to_page_end = page_size - current_addr % page_size;
+            let chunk_len = (to_page_end as usize).min(max_len - bytes.len());
+
+            let mut chunk = vec![0; chunk_len];
+            self.copy_from_user(memory, current_addr, &mut chunk)?;
+
+            if let Some(nul) = chunk.iter().position(|byte| *byte == 0) {
+                bytes.extend_from_slice(&chunk[..nul]);
+                return Ok(CString::new(bytes).unwrap());
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        Err(io::Error::from_raw_os_error(ENAMETOOLONG))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{EFAULT, ENAMETOOLONG, PAGESIZE, PROT_READ, PROT_WRITE};
+    use crate::types::{MemoryBackingType, VmmapOps};
+    use crate::vmmap::Vmmap;
+
+    use super::BasePointerMemory;
+
+    const PAGE: usize = PAGESIZE as usize;
+
+    /// Pages 0-1 are read/write, page 2 is read only, page 3 is unmapped
+    fn create_vmmap() -> Vmmap {
+        let mut vmmap = Vmmap::new();
+        for (page_num, prot) in [(0, PROT_READ | PROT_WRITE), (2, PROT_READ)] {
+            let npages = if page_num == 0 { 2 } else { 1 };
+            vmmap
+                .add_entry_with_override(
+                    page_num,
+                    npages,
+                    prot,
+                    prot,
+                    0,
+                    MemoryBackingType::Anonymous,
+                    0,
+                    0,
+                    1,
+                )
+                .unwrap();
+        }
+        vmmap
+    }
+
+    #[test]
+    fn test_copy_to_and_from_user() {
+        let mut vmmap = create_vmmap();
+        let mut memory = vec![0u8; 4 * PAGE];
+
+        vmmap
+            .copy_to_user(memory.as_mut_slice(), PAGE as u64 - 2, b"abcd")
+            .unwrap();
+        let mut buf = [0; 4];
+        vmmap
+            .copy_from_user(memory.as_slice(), PAGE as u64 - 2, &mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"abcd");
+
+        // read only and unmapped pages are rejected before anything is copied
+        let err = vmmap
+            .copy_to_user(memory.as_mut_slice(), 2 * PAGE as u64 - 2, b"abcd")
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(EFAULT));
+        assert_eq!(&memory[2 * PAGE - 2..2 * PAGE], &[0, 0]);
+
+        let err = vmmap
+            .copy_from_user(memory.as_slice(), 3 * PAGE as u64 - 2, &mut buf)
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(EFAULT));
+
+        // the same checks apply when going through a raw base pointer
+        let mut raw = unsafe { BasePointerMemory::new(memory.as_mut_ptr()) };
+        vmmap.copy_to_user(&mut raw, 16, b"raw").unwrap();
+        assert_eq!(&memory[16..19], b"raw");
+    }
+
+    #[test]
+    fn test_read_user_string() {
+        let mut vmmap = create_vmmap();
+        let mut memory = vec![b'a'; 4 * PAGE];
+
+        // string ending on the last byte of the last mapped page
+        memory[3 * PAGE - 1] = 0;
+        let start = 3 * PAGE as u64 - 6;
+        let path = vmmap
+            .read_user_string(memory.as_slice(), start, 4096)
+            .unwrap();
+        assert_eq!(path.as_bytes(), b"aaaaa");
+
+        let err = vmmap
+            .read_user_string(memory.as_slice(), start, 3)
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENAMETOOLONG));
+
+        // string running into the unmapped page
+        memory[3 * PAGE - 1] = b'a';
+        let err = vmmap
+            .read_user_string(memory.as_slice(), start, 4096)
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(EFAULT));
+    }
+}
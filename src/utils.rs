@@ -0,0 +1,33 @@
+/// Small seedable pseudo random generator (SplitMix64) used for randomized
+/// placement. It is not cryptographically secure, but the same seed always
+/// yields the same sequence which keeps tests and replay runs deterministic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        SeededRng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a value uniformly distributed in `[0, bound)`, bound must be non zero
+    pub fn below(&mut self, bound: u64) -> u64 {
+        // reject the top partial range so that every value is equally likely
+        let zone = u64::MAX - (u64::MAX % bound);
+        loop {
+            let value = self.next_u64();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::io;
+
+use crate::constants::{EACCES, EINVAL, MAP_ANONYMOUS};
+use crate::store::EntryStore;
+use crate::types::{MemoryBackingType, VmmapGeometry};
+use crate::vmmap::Vmmap;
+
+/// Checks the attributes of a mapping about to be created, so that no entry
+/// ends up breaking the invariants `Vmmap::validate` checks. Fails with EACCES
+/// if `prot` exceeds `maxprot`, and with EINVAL if the file offset is negative
+/// or not page aligned, or the backing does not agree with the flags or offset.
+pub(crate) fn check_mapping(
+    prot: i32,
+    maxprot: i32,
+    flags: i32,
+    backing: MemoryBackingType,
+    file_offset: i64,
+    geometry: &VmmapGeometry,
+) -> Result<(), io::Error> {
+    if prot & !maxprot != 0 {
+        return Err(io::Error::from_raw_os_error(EACCES));
+    }
+    let consistent = match backing {
+        MemoryBackingType::FileDescriptor(_) => {
+            flags & MAP_ANONYMOUS as i32 == 0
+                && file_offset >= 0
+                && (file_offset as u64).is_multiple_of(geometry.page_size())
+        }
+        MemoryBackingType::SharedMemory(_) => {
+            file_offset >= 0 && (file_offset as u64).is_multiple_of(geometry.page_size())
+        }
+        MemoryBackingType::Anonymous | MemoryBackingType::None => file_offset == 0,
+    };
+    if !consistent {
+        return Err(io::Error::from_raw_os_error(EINVAL));
+    }
+    Ok(())
+}
+
+impl<S: EntryStore> Vmmap<S> {
+    /// Checks the internal consistency of the vmmap: every entry describes exactly
+    /// the pages of its interval, none is empty, its protection stays within its
+    /// maximum protection, its file offset and flags agree with its backing, its
+    /// page tracking covers all its pages, and the cached entry still matches an
+    /// entry. Returns an InvalidData error describing the first violation found.
+    pub fn validate(&self) -> Result<(), io::Error> {
+        for (interval, entry) in self.entries.iter() {
+            let violation = |what: &str| {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "entry [{}, {}) {what}: {entry:?}",
+                        interval.start(),
+                        interval.end() + 1
+                    ),
+                ))
+            };
+
+            if entry.npages == 0 || interval.end() < interval.start() {
+                return violation("is empty");
+            }
+            if entry.page_num != interval.start()
+                || entry.npages != interval.end() + 1 - interval.start()
+            {
+                return violation("does not match its interval");
+            }
+            if let Err(err) = check_mapping(
+                entry.prot,
+                entry.maxprot,
+                entry.flags,
+                entry.backing,
+                entry.file_offset,
+                &self.geometry,
+            ) {
+                return match err.raw_os_error() {
+                    Some(EACCES) => violation("has a protection beyond its maximum"),
+                    _ => violation("has a file offset or flags its backing does not allow"),
+                };
+            }
+            if entry.page_tracking.as_ref().is_some_and(|tracking| {
+                [&tracking.accessed, &tracking.dirty]
+                    .iter()
+                    .any(|bits| (bits.len() as u64) * 64 < entry.npages)
+            }) {
+                return violation("tracks fewer pages than it maps");
+            }
+        }
+
+        if let Some(cached) = &self.cached_entry {
+            let cached_end = cached.page_num + cached.npages;
+            let matches = self
+                .entries
+                .get_key_value_at_point(cached.page_num)
+                .is_some_and(|(interval, entry)| {
+                    interval.start() == cached.page_num
+                        && interval.end() + 1 == cached_end
+                        && entry.prot == cached.prot
+                });
+            if !matches {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "cached entry [{}, {cached_end}) no longer matches an entry",
+                        cached.page_num
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Panics if `validate` fails, in debug builds only. Called after every
+    /// operation that changes the entries.
+    pub(crate) fn debug_validate(&self) {
+        if cfg!(debug_assertions) {
+            if let Err(err) = self.validate() {
+                panic!("vmmap invariant violated: {err}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use nodit::interval::ie;
+
+    use crate::constants::{
+        EACCES, EINVAL, MAP_SHARED, PAGESIZE, PROT_EXEC, PROT_READ, PROT_WRITE,
+    };
+    use crate::types::{MemoryBackingType, PageState, VmmapOps};
+    use crate::vmmap::Vmmap;
+
+    fn map(
+        vmmap: &mut Vmmap,
+        page_num: u64,
+        prot: i32,
+        backing: MemoryBackingType,
+        file_offset: i64,
+    ) -> Result<(), io::Error> {
+        vmmap.add_entry_with_override(
+            page_num,
+            10,
+            prot,
+            PROT_READ | PROT_WRITE,
+            MAP_SHARED as i32,
+            backing,
+            file_offset,
+            1 << 20,
+            1,
+        )
+    }
+
+    #[test]
+    fn test_split_entries_stay_consistent() {
+        let mut vmmap = Vmmap::new();
+        map(
+            &mut vmmap,
+            0,
+            PROT_READ,
+            MemoryBackingType::FileDescriptor(3),
+            PAGESIZE as i64,
+        )
+        .unwrap();
+        vmmap.mark_pages(2, 1, PageState::Dirty).unwrap();
+        vmmap.mark_pages(6, 1, PageState::Dirty).unwrap();
+
+        vmmap.change_prot(4, 2, PROT_READ | PROT_WRITE).unwrap();
+        vmmap.validate().unwrap();
+        let upper = vmmap.entries.get_at_point(6).unwrap();
+        assert_eq!((upper.page_num, upper.npages), (6, 4));
+        assert_eq!(upper.file_offset, 7 * PAGESIZE as i64);
+        assert_eq!(vmmap.pages_in_state(0, 10, PageState::Dirty), [2, 6]);
+
+        // joined back into one entry
+        vmmap.change_prot(4, 2, PROT_READ).unwrap();
+        vmmap.validate().unwrap();
+        assert_eq!(vmmap.entries.len(), 1);
+        assert_eq!(vmmap.pages_in_state(0, 10, PageState::Dirty), [2, 6]);
+
+        vmmap.remove_entry(3, 4).unwrap();
+        vmmap.validate().unwrap();
+        assert!(vmmap.entries.contains_interval(ie(7, 10)));
+        assert_eq!(vmmap.pages_in_state(0, 10, PageState::Dirty), [2]);
+
+        // corruption from outside is reported
+        let tracking = vmmap
+            .find_page_mut(0)
+            .unwrap()
+            .page_tracking
+            .as_mut()
+            .unwrap();
+        let dirty = std::mem::take(&mut tracking.dirty);
+        let err = vmmap.validate().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        vmmap
+            .find_page_mut(0)
+            .unwrap()
+            .page_tracking
+            .as_mut()
+            .unwrap()
+            .dirty = dirty;
+        vmmap.validate().unwrap();
+        vmmap.find_page_mut(8).unwrap().page_num = 8;
+        let err = vmmap.validate().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_inconsistent_mappings_are_refused() {
+        let mut vmmap = Vmmap::new();
+        let errno = |result: Result<(), io::Error>| result.unwrap_err().raw_os_error();
+
+        assert_eq!(
+            errno(vmmap.add_entry_with_override(
+                0,
+                10,
+                PROT_READ | PROT_WRITE,
+                PROT_READ,
+                0,
+                MemoryBackingType::Anonymous,
+                0,
+                0,
+                1,
+            )),
+            Some(EACCES)
+        );
+        assert_eq!(
+            errno(map(
+                &mut vmmap,
+                0,
+                PROT_READ,
+                MemoryBackingType::FileDescriptor(3),
+                17,
+            )),
+            Some(EINVAL)
+        );
+        assert_eq!(
+            errno(map(
+                &mut vmmap,
+                0,
+                PROT_READ,
+                MemoryBackingType::Anonymous,
+                PAGESIZE as i64,
+            )),
+            Some(EINVAL)
+        );
+        assert!(vmmap.entries.is_empty());
+
+        map(&mut vmmap, 0, PROT_READ, MemoryBackingType::Anonymous, 0).unwrap();
+        let entries = vmmap.entries.clone();
+        assert_eq!(
+            errno(vmmap.change_prot(2, 4, PROT_READ | PROT_EXEC)),
+            Some(EACCES)
+        );
+        assert_eq!(&vmmap.entries, &entries);
+        vmmap.validate().unwrap();
+    }
+}
@@ -1,38 +1,603 @@
 use std::io;
 
-use nodit::NoditMap;
-use nodit::{interval::ie, Interval};
+use nodit::{interval::ie, Interval, NoditMap};
 
+use crate::commit::{is_commit_charged, SharedCommitRegistry};
 use crate::constants::{
     // MAP_PRIVATE, O_ACCMODE, O_RDONLY, O_RDWR, O_WRONLY, PAGESIZE,
+    EACCES,
+    EAGAIN,
+    EEXIST,
+    EFAULT,
+    EINVAL,
+    ENOMEM,
     PROT_EXEC,
     PROT_NONE,
     PROT_READ,
     PROT_WRITE,
 };
-use crate::types::{MemoryBackingType, VmmapEntry, VmmapOps};
+use crate::journal::Journal;
+use crate::memory::SimulatedMemory;
+use crate::placement::{FirstFit, PlacementStrategy, Random, TopDown};
+use crate::store::EntryStore;
+use crate::types::{
+    AccessKind, Advice, DiscardCallback, FaultClass, IoVec, MemoryBackingType, ObserverCallback,
+    PageTracking, VmmapEntry, VmmapEvent, VmmapGeometry, VmmapOp, VmmapOps, WritebackCallback,
+};
+use crate::validate::check_mapping;
+
+/// Protection bit an access of the given kind requires
+pub(crate) fn access_prot(access: AccessKind) -> i32 {
+    match access {
+        AccessKind::Read => PROT_READ,
+        AccessKind::Write => PROT_WRITE,
+        AccessKind::Exec => PROT_EXEC,
+    }
+}
+
+/// Protection accesses to a mapping are checked against: any accessible mapping
+/// is implicitly readable
+fn accessible_prot(prot: i32) -> i32 {
+    if prot & (PROT_EXEC | PROT_READ | PROT_WRITE) != PROT_NONE {
+        prot | PROT_READ
+    } else {
+        prot
+    }
+}
 
-pub struct Vmmap {
-    pub entries: NoditMap<u32, Interval<u32>, VmmapEntry>, // Keyed by `page_num`
-    pub cached_entry: Option<VmmapEntry>,                  // TODO: is this still needed?
-                                                           // Use Option for safety
+/// Size of the default cage window in bytes
+const DEFAULT_WINDOW_SIZE: u64 = 1 << 32;
+
+/// Default entry store of a vmmap
+pub type DefaultStore = NoditMap<u64, Interval<u64>, VmmapEntry>;
+
+pub struct Vmmap<S: EntryStore = DefaultStore> {
+    pub entries: S, // Keyed by `page_num`, see `Vmmap::with_store`
+    // TODO: is this still needed? Use Option for safety
+    pub cached_entry: Option<VmmapEntry>,
+    pub start_page: u64, // lowest page placement searches may hand out
+    pub end_page: u64,   // one past the highest page placement searches may hand out
+    pub placement: Box<dyn PlacementStrategy>, // decides where non-fixed mappings go
+    pub geometry: VmmapGeometry, // page size and map granularity
+    pub memory: Option<SimulatedMemory>, // Some when running against simulated memory
+    pub memlock_limit: Option<u64>, // RLIMIT_MEMLOCK in pages, None if unlimited
+    pub lock_future: bool, // set by mlockall(MCL_FUTURE)
+    pub address_space_limit: Option<u64>, // RLIMIT_AS in pages, None if unlimited
+    pub discard_callback: Option<DiscardCallback>, // see `Vmmap::set_discard_callback`
+    pub writeback_callback: Option<WritebackCallback>, // see `Vmmap::set_writeback_callback`
+    pub(crate) commit_charge: u64, // pages charged, see `Vmmap::commit_charge`
+    pub(crate) commit_registry: Option<SharedCommitRegistry>, // shared with the other cages
+    pub(crate) observers: Vec<(u64, ObserverCallback)>, // see `Vmmap::add_observer`
+    pub(crate) next_observer_id: u64,
+    pub(crate) pending_events: Option<Vec<VmmapEvent>>, // held back while a transaction applies
+    pub(crate) journal: Option<Journal>,                // see `Vmmap::start_journal`
 }
 
 #[allow(dead_code)]
 impl Vmmap {
+    /// Creates a vmmap covering a 4 GiB window of 4 KiB pages
     pub fn new() -> Self {
+        let geometry = VmmapGeometry::default();
+        Self::with_bounds(0, DEFAULT_WINDOW_SIZE >> geometry.page_shift)
+    }
+
+    /// Creates a vmmap whose placement searches are restricted to pages in
+    /// `[start_page, end_page)`, e.g. the window a cage is allowed to use
+    pub fn with_bounds(start_page: u64, end_page: u64) -> Self {
+        Self::with_geometry(VmmapGeometry::default(), start_page, end_page)
+    }
+
+    /// Same as `with_bounds`, but for hosts whose page size or mmap granularity
+    /// differ from the native client defaults. Page numbers are in units of
+    /// `geometry.page_size()`.
+    pub fn with_geometry(geometry: VmmapGeometry, start_page: u64, end_page: u64) -> Self {
+        Self::with_store(DefaultStore::new(), geometry, start_page, end_page)
+    }
+}
+
+#[allow(dead_code)]
+impl<S: EntryStore> Vmmap<S> {
+    /// Same as `with_geometry`, but keeps the entries in `store` instead of the
+    /// default interval tree, e.g. a `BTreeStore` or `PageTableStore`. `store` is
+    /// expected to be empty.
+    pub fn with_store(store: S, geometry: VmmapGeometry, start_page: u64, end_page: u64) -> Self {
+        assert!(start_page < end_page, "vmmap bounds must not be empty");
         Vmmap {
-            entries: NoditMap::new(),
+            entries: store,
             cached_entry: None,
+            start_page,
+            end_page,
+            placement: Box::new(FirstFit),
+            geometry,
+            memory: None,
+            memlock_limit: None,
+            lock_future: false,
+            address_space_limit: None,
+            discard_callback: None,
+            writeback_callback: None,
+            commit_charge: 0,
+            commit_registry: None,
+            observers: Vec::new(),
+            next_observer_id: 0,
+            pending_events: None,
+            journal: None,
+        }
+    }
+
+    /// Replaces the policy used by `find_space`, `find_map_space` and their hint variants
+    pub fn set_placement(&mut self, placement: impl PlacementStrategy + 'static) {
+        self.placement = Box::new(placement);
+    }
+
+    /// Switches `find_space` and `find_map_space` to place mappings top-down, starting
+    /// right below `mmap_base` (usually the bottom of the stack guard gap)
+    pub fn set_top_down(&mut self, mmap_base: u64) {
+        self.set_placement(TopDown::new(mmap_base));
+    }
+
+    /// Makes `find_space` and `find_map_space` pick a random suitably aligned slot
+    /// among all gaps that can fit the request.
+    /// The same seed always produces the same sequence of placements.
+    pub fn set_randomized(&mut self, seed: u64) {
+        self.set_placement(Random::new(seed));
+    }
+
+    /// Free gaps of the bounded address space at or above `hint`, in ascending order
+    fn free_gaps(&self, hint: u64) -> Vec<Interval<u64>> {
+        match self.search_interval(hint) {
+            Some(search_interval) => self.entries.gaps_trimmed(search_interval),
+            None => Vec::new(),
+        }
+    }
+
+    /// Interval of the bounded address space starting at `hint`, or None if the
+    /// hint lies at or above the upper bound
+    fn search_interval(&self, hint: u64) -> Option<Interval<u64>> {
+        let start = hint.max(self.start_page);
+        if start >= self.end_page {
+            return None;
+        }
+        Some(ie(start, self.end_page))
+    }
+
+    /// The cached entry and its accessible protection, if it covers all of
+    /// `[page_num, end_page)`
+    fn cached_lookup(&self, page_num: u64, end_page: u64) -> Option<(&VmmapEntry, i32)> {
+        let cached_entry = self.cached_entry.as_ref()?;
+        let covers = cached_entry.page_num <= page_num
+            && end_page <= cached_entry.page_num + cached_entry.npages;
+        covers.then(|| (cached_entry, accessible_prot(cached_entry.prot)))
+    }
+
+    /// Entry covering `page_num` and its accessible protection, looked up the same
+    /// way `check_addr_mapping` does: the cached entry first, then the map
+    fn lookup(&self, page_num: u64) -> Option<(&VmmapEntry, i32)> {
+        self.cached_lookup(page_num, page_num.saturating_add(1))
+            .or_else(|| {
+                let entry = self.entries.get_at_point(page_num)?;
+                Some((entry, accessible_prot(entry.prot)))
+            })
+    }
+
+    /// Copy of `entry` describing only the pages in `interval`, e.g. one of the
+    /// pieces left behind when the entry is split. For file and shared memory
+    /// backings the offset moves along with the start page, and recorded page
+    /// state is sliced down to the piece.
+    fn entry_for_interval(&self, interval: Interval<u64>, entry: &VmmapEntry) -> VmmapEntry {
+        let mut piece = entry.clone();
+        piece.page_num = interval.start();
+        piece.npages = interval.end() + 1 - interval.start();
+        if let MemoryBackingType::FileDescriptor(_) | MemoryBackingType::SharedMemory(_) =
+            entry.backing
+        {
+            let skipped_bytes = self
+                .geometry
+                .pages_to_bytes(interval.start() - entry.page_num)
+                .and_then(|bytes| i64::try_from(bytes).ok())
+                .unwrap_or(i64::MAX);
+            piece.file_offset = piece.file_offset.saturating_add(skipped_bytes);
+        }
+        if let Some(tracking) = &entry.page_tracking {
+            piece.page_tracking =
+                Some(tracking.sliced(interval.start() - entry.page_num, piece.npages));
+        }
+        piece
+    }
+
+    /// Makes sure an interval boundary falls on `page_num` by splitting the entry
+    /// covering it in two. Each half describes its own pages, see `entry_for_interval`.
+    pub(crate) fn split_at(&mut self, page_num: u64) {
+        let Some((interval, entry)) = self.split_entry(page_num) else {
+            return;
+        };
+        if self.observed() {
+            self.notify(vec![VmmapEvent::Split {
+                interval,
+                page_num,
+                entry,
+            }]);
+        }
+    }
+
+    /// Body of `split_at` without the event, returning the interval and entry that
+    /// were split, if any
+    fn split_entry(&mut self, page_num: u64) -> Option<(Interval<u64>, VmmapEntry)> {
+        let (interval, entry) = self.entries.get_key_value_at_point(page_num)?;
+        if interval.start() == page_num {
+            return None;
+        }
+        let (interval, entry) = (*interval, entry.clone());
+
+        let lower_interval = ie(interval.start(), page_num);
+        let upper_interval = ie(page_num, interval.end() + 1);
+        let lower = self.entry_for_interval(lower_interval, &entry);
+        let upper = self.entry_for_interval(upper_interval, &entry);
+
+        self.cached_entry = None;
+        let _ = self.entries.remove_overlapping(interval);
+        let _ = self.entries.insert_strict(lower_interval, lower);
+        let _ = self.entries.insert_strict(upper_interval, upper);
+        Some((interval, entry))
+    }
+
+    /// Joins the entries around `[start_page, end_page)` with their neighbours
+    /// when they continue the same mapping, which happens once pieces split off
+    /// a mapping are given back their original attributes
+    pub(crate) fn merge_around(&mut self, start_page: u64, end_page: u64) {
+        let around = ie(start_page.saturating_sub(1), end_page.saturating_add(1));
+        let pieces: Vec<(Interval<u64>, VmmapEntry)> = self
+            .entries
+            .overlapping(around)
+            .map(|(interval, entry)| (*interval, entry.clone()))
+            .collect();
+
+        let mut runs: Vec<Vec<(Interval<u64>, VmmapEntry)>> = Vec::new();
+        for piece in pieces {
+            match runs.last_mut() {
+                Some(run)
+                    if run.last().is_some_and(|(interval, entry)| {
+                        interval.end() + 1 == piece.0.start()
+                            && continues(entry, &piece.1, &self.geometry)
+                    }) =>
+                {
+                    run.push(piece)
+                }
+                _ => runs.push(vec![piece]),
+            }
+        }
+
+        let mut events = Vec::new();
+        for run in runs.into_iter().filter(|run| run.len() > 1) {
+            let merged = ie(run[0].0.start(), run[run.len() - 1].0.end() + 1);
+            let tracking = |entry: &VmmapEntry| {
+                (entry.page_tracking.clone()).unwrap_or_else(|| PageTracking::new(entry.npages))
+            };
+            let entry = run[1..]
+                .iter()
+                .fold(run[0].1.clone(), |mut lower, (_, upper)| {
+                    if lower.page_tracking.is_some() || upper.page_tracking.is_some() {
+                        let total = lower.npages + upper.npages;
+                        lower.page_tracking =
+                            Some(tracking(&lower).joined(lower.npages, &tracking(upper), total));
+                    }
+                    lower.npages += upper.npages;
+                    lower
+                });
+            self.cached_entry = None;
+            let _ = self.entries.remove_overlapping(merged);
+            let _ = self.entries.insert_strict(merged, entry.clone());
+            if self.observed() {
+                events.push(VmmapEvent::Merged {
+                    intervals: run.iter().map(|(interval, _)| *interval).collect(),
+                    interval: merged,
+                    entry,
+                });
+            }
+        }
+        if !events.is_empty() {
+            self.notify(events);
         }
     }
 
-    fn round_page_num_up_to_map_multiple(&self, npages: u32, pages_per_map: u32) -> u32 {
-        (npages + pages_per_map - 1) & !(pages_per_map - 1)
+    /// Checks that every byte of `[addr, addr + len)` is mapped and allows `access`,
+    /// failing with EFAULT otherwise. Zero length buffers are always valid, the same
+    /// as for the kernel's `access_ok`.
+    pub fn validate_user_range(
+        &mut self,
+        addr: u64,
+        len: u64,
+        access: AccessKind,
+    ) -> Result<(), io::Error> {
+        if len == 0 {
+            return Ok(());
+        }
+
+        let end = addr
+            .checked_add(len)
+            .ok_or_else(|| io::Error::from_raw_os_error(EFAULT))?;
+        let first_page = self.geometry.addr_to_page(addr);
+        let last_page = self.geometry.addr_to_page(end - 1);
+
+        match self.check_addr_mapping(first_page, last_page - first_page + 1, access_prot(access)) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::from_raw_os_error(EFAULT)),
+        }
+    }
+
+    /// Same as `validate_user_range` for every buffer of an iovec array. The combined
+    /// length must fit in an `isize`, otherwise EINVAL is returned like readv/writev do.
+    pub fn validate_user_iovecs(
+        &mut self,
+        iovecs: &[IoVec],
+        access: AccessKind,
+    ) -> Result<(), io::Error> {
+        let mut total_len: u64 = 0;
+        for iovec in iovecs {
+            total_len = total_len
+                .checked_add(iovec.iov_len)
+                .filter(|total| *total <= isize::MAX as u64)
+                .ok_or_else(|| io::Error::from_raw_os_error(EINVAL))?;
+        }
+
+        for iovec in iovecs {
+            self.validate_user_range(iovec.iov_base, iovec.iov_len, access)?;
+        }
+
+        Ok(())
     }
 
-    fn trunc_page_num_down_to_map_multiple(&self, npages: u32, pages_per_map: u32) -> u32 {
-        npages & !(pages_per_map - 1)
+    /// Duplicates the address space for a child cage the way fork does. Mappings
+    /// advised MADV_DONTFORK are left out, MADV_WIPEONFORK ones are handed to the
+    /// child zero filled, and memory locks are not inherited. The child starts out
+    /// with first fit placement and without a discard callback, and keeps its
+    /// entries in the same kind of store as the parent. Fails with ENOMEM if the
+    /// commit charge of the child is refused.
+    pub fn fork(&self, child_cage_id: u64) -> Result<Self, io::Error> {
+        let mut child =
+            Self::with_store(S::default(), self.geometry, self.start_page, self.end_page);
+        child.memlock_limit = self.memlock_limit;
+        child.address_space_limit = self.address_space_limit;
+        child.memory = self.memory.clone();
+        child.commit_registry = self.commit_registry.clone();
+
+        for (interval, entry) in self.entries.iter() {
+            let (start_page, end_page) = (interval.start(), interval.end() + 1);
+            if entry.advice.dont_fork || entry.advice.wipe_on_fork {
+                if let Some(memory) = &mut child.memory {
+                    memory.discard(start_page, end_page);
+                }
+            }
+            if entry.advice.dont_fork {
+                continue;
+            }
+
+            let mut child_entry = entry.clone();
+            child_entry.cage_id = child_cage_id;
+            child_entry.locked = false;
+            if entry.advice.wipe_on_fork {
+                child_entry.page_tracking = None;
+            }
+            let _ = child.entries.insert_strict(*interval, child_entry);
+        }
+        let charge = child.charged_pages(0, u64::MAX, None);
+        child.recharge(0, charge)?;
+
+        child.debug_validate();
+        Ok(child)
+    }
+
+    /// Body of `update`, which also records the call in the journal
+    #[allow(clippy::too_many_arguments)]
+    fn update_entries(
+        &mut self,
+        page_num: u64,
+        npages: u64,
+        prot: i32,
+        maxprot: i32,
+        flags: i32,
+        backing: MemoryBackingType,
+        remove: bool,
+        file_offset: i64,
+        file_size: i64,
+        cage_id: u64,
+    ) -> Result<(), io::Error> {
+        if npages == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Number of pages cannot be zero",
+            ));
+        }
+
+        if !remove {
+            check_mapping(prot, maxprot, flags, backing, file_offset, &self.geometry)?;
+        }
+
+        let new_region_end_page = page_num
+            .checked_add(npages)
+            .ok_or_else(|| io::Error::from_raw_os_error(EINVAL))?;
+        let new_region_start_page = page_num; // just for ease of understanding
+
+        if !remove && !self.address_space_allows(new_region_start_page, new_region_end_page) {
+            return Err(io::Error::from_raw_os_error(ENOMEM));
+        }
+        // after mlockall(MCL_FUTURE) new mappings are locked and count against the limit
+        let locked = self.lock_future && !remove;
+        if locked && !self.memlock_allows(new_region_start_page, new_region_end_page) {
+            return Err(io::Error::from_raw_os_error(EAGAIN));
+        }
+
+        // private writable pages are charged against the commit limit
+        let old_charge = self.charged_pages(new_region_start_page, new_region_end_page, None);
+        let new_charge = if !remove && is_commit_charged(flags, prot) {
+            npages
+        } else {
+            0
+        };
+        self.recharge(old_charge, new_charge)?;
+
+        // faults are classified through the cached entry, so it must not outlive a change
+        self.cached_entry = None;
+
+        // whatever was mapped here before is gone, fresh anonymous pages read as zero
+        if let Some(memory) = &mut self.memory {
+            memory.discard(new_region_start_page, new_region_end_page);
+        }
+
+        // entries straddling either end of the region keep their pages outside of it
+        self.split_entry(new_region_start_page);
+        self.split_entry(new_region_end_page);
+
+        // Insert the new entry if not marked for removal
+        let new_entry = VmmapEntry {
+            page_num,
+            npages,
+            prot,
+            maxprot,
+            flags,
+            backing,
+            file_offset,
+            file_size,
+            removed: false,
+            cage_id,
+            page_tracking: None,
+            locked,
+            advice: Advice::default(),
+        };
+        let mut events = Vec::new();
+        if self.observed() {
+            events.extend(
+                self.entries
+                    .overlapping(ie(new_region_start_page, new_region_end_page))
+                    .map(|(interval, entry)| VmmapEvent::Unmapped {
+                        interval: *interval,
+                        entry: entry.clone(),
+                    }),
+            );
+            if !remove {
+                events.push(VmmapEvent::Mapped {
+                    interval: ie(new_region_start_page, new_region_end_page),
+                    entry: new_entry.clone(),
+                });
+            }
+        }
+
+        let _ = self
+            .entries
+            .remove_overlapping(ie(new_region_start_page, new_region_end_page));
+        if !remove {
+            let _ = self
+                .entries
+                .insert_strict(ie(new_region_start_page, new_region_end_page), new_entry);
+        }
+
+        if !events.is_empty() {
+            self.notify(events);
+        }
+
+        Ok(())
+    }
+
+    /// Body of `add_entry`, which also records the call in the journal
+    fn insert_entry(&mut self, vmmap_entry_ref: VmmapEntry) -> Result<(), io::Error> {
+        if vmmap_entry_ref.npages == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Number of pages cannot be zero",
+            ));
+        }
+        check_mapping(
+            vmmap_entry_ref.prot,
+            vmmap_entry_ref.maxprot,
+            vmmap_entry_ref.flags,
+            vmmap_entry_ref.backing,
+            vmmap_entry_ref.file_offset,
+            &self.geometry,
+        )?;
+        let end_page = vmmap_entry_ref
+            .page_num
+            .checked_add(vmmap_entry_ref.npages)
+            .ok_or_else(|| io::Error::from_raw_os_error(EINVAL))?;
+
+        // unlike `update`, existing mappings are never replaced
+        let interval = ie(vmmap_entry_ref.page_num, end_page);
+        if self.entries.overlaps(interval) {
+            return Err(io::Error::from_raw_os_error(EEXIST));
+        }
+        if !self.address_space_allows(vmmap_entry_ref.page_num, end_page) {
+            return Err(io::Error::from_raw_os_error(ENOMEM));
+        }
+        let charge = if is_commit_charged(vmmap_entry_ref.flags, vmmap_entry_ref.prot) {
+            vmmap_entry_ref.npages
+        } else {
+            0
+        };
+        self.recharge(0, charge)?;
+        // pages x to y, y included
+        let _ = self.entries.insert_strict(interval, vmmap_entry_ref);
+        Ok(())
+    }
+
+    /// Body of `change_prot`, which also records the call in the journal
+    fn change_entries_prot(
+        &mut self,
+        page_num: u64,
+        npages: u64,
+        new_prot: i32,
+    ) -> Result<(), io::Error> {
+        if npages == 0 {
+            return Ok(());
+        }
+
+        let new_region_end_page = page_num
+            .checked_add(npages)
+            .ok_or_else(|| io::Error::from_raw_os_error(EINVAL))?;
+        let new_region_start_page = page_num;
+
+        // like mprotect, protections beyond the maximum of a mapping are refused
+        if self
+            .entries
+            .overlapping(ie(new_region_start_page, new_region_end_page))
+            .any(|(_, entry)| new_prot & !entry.maxprot != 0)
+        {
+            return Err(io::Error::from_raw_os_error(EACCES));
+        }
+
+        // making private pages writable charges them, so it can be refused
+        let old_charge = self.charged_pages(new_region_start_page, new_region_end_page, None);
+        let new_charge =
+            self.charged_pages(new_region_start_page, new_region_end_page, Some(new_prot));
+        self.recharge(old_charge, new_charge)?;
+
+        // entries straddling either end of the region keep their old protection
+        // outside of it, so split them at the boundaries first
+        self.split_at(new_region_start_page);
+        self.split_at(new_region_end_page);
+        self.cached_entry = None;
+
+        let observed = self.observed();
+        let mut events = Vec::new();
+        for (interval, entry) in self
+            .entries
+            .overlapping_mut(ie(new_region_start_page, new_region_end_page))
+        {
+            let old_prot = entry.prot;
+            entry.prot = new_prot;
+            if observed && old_prot != new_prot {
+                events.push(VmmapEvent::ProtectionChanged {
+                    interval: *interval,
+                    old_prot,
+                    entry: entry.clone(),
+                });
+            }
+        }
+        if !events.is_empty() {
+            self.notify(events);
+        }
+
+        // pieces given back the protection of their neighbours join them again
+        self.merge_around(new_region_start_page, new_region_end_page);
+
+        Ok(())
     }
 
     fn visit() {}
@@ -40,22 +605,35 @@ impl Vmmap {
     fn debug() {}
 }
 
-impl VmmapOps for Vmmap {
-    fn add_entry(&mut self, vmmap_entry_ref: VmmapEntry) {
-        let _ = self.entries.insert_strict(
-            // pages x to y, y included
-            ie(
-                vmmap_entry_ref.page_num,
-                vmmap_entry_ref.page_num + vmmap_entry_ref.npages,
-            ),
-            vmmap_entry_ref,
-        );
+impl Default for Vmmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: EntryStore> VmmapOps for Vmmap<S> {
+    fn add_entry(&mut self, vmmap_entry_ref: VmmapEntry) -> Result<(), io::Error> {
+        let op = VmmapOp::Insert {
+            page_num: vmmap_entry_ref.page_num,
+            npages: vmmap_entry_ref.npages,
+            prot: vmmap_entry_ref.prot,
+            maxprot: vmmap_entry_ref.maxprot,
+            flags: vmmap_entry_ref.flags,
+            backing: vmmap_entry_ref.backing,
+            file_offset: vmmap_entry_ref.file_offset,
+            file_size: vmmap_entry_ref.file_size,
+            cage_id: vmmap_entry_ref.cage_id,
+        };
+        let result = self.insert_entry(vmmap_entry_ref);
+        self.record(op, &result);
+        self.debug_validate();
+        result
     }
 
     fn add_entry_with_override(
         &mut self,
-        page_num: u32,
-        npages: u32,
+        page_num: u64,
+        npages: u64,
         prot: i32,
         maxprot: i32,
         flags: i32,
@@ -81,10 +659,10 @@ impl VmmapOps for Vmmap {
     /// This function will not return any errors pertaining to the page number not mapping
     /// to any existing pages, as the remove operation is done on a best efforts basis:
     /// 1. First an insert overwrite operation with the below page range is performed, causing
-    /// a new interval to be created over the provided page range, appropriately partitioning
-    /// boundary pages.
+    ///    a new interval to be created over the provided page range, appropriately partitioning
+    ///    boundary pages.
     /// 2. This new interval is then deleted, leaving the underlying range unmapped
-    fn remove_entry(&mut self, page_num: u32, npages: u32) -> Result<(), io::Error> {
+    fn remove_entry(&mut self, page_num: u64, npages: u64) -> Result<(), io::Error> {
         self.update(
             page_num,
             npages,
@@ -101,8 +679,8 @@ impl VmmapOps for Vmmap {
 
     fn update(
         &mut self,
-        page_num: u32,
-        npages: u32,
+        page_num: u64,
+        npages: u64,
         prot: i32,
         maxprot: i32,
         flags: i32,
@@ -112,78 +690,58 @@ impl VmmapOps for Vmmap {
         file_size: i64,
         cage_id: u64,
     ) -> Result<(), io::Error> {
-        if npages == 0 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Number of pages cannot be zero",
-            ));
-        }
-
-        let new_region_end_page = page_num + npages;
-        let new_region_start_page = page_num; // just for ease of understanding
-
-        // Insert the new entry if not marked for removal
-        let new_entry = VmmapEntry {
+        let result = self.update_entries(
             page_num,
             npages,
             prot,
             maxprot,
             flags,
             backing,
+            remove,
             file_offset,
             file_size,
-            removed: false,
             cage_id,
+        );
+        let op = if remove {
+            VmmapOp::Unmap { page_num, npages }
+        } else {
+            VmmapOp::Map {
+                page_num,
+                npages,
+                prot,
+                maxprot,
+                flags,
+                backing,
+                file_offset,
+                file_size,
+                cage_id,
+            }
         };
-        let _ = self
-            .entries
-            .insert_overwrite(ie(new_region_start_page, new_region_end_page), new_entry);
-
-        if remove {
-            // strange way to do this, but this is the best using the library we have at hand
-            // while also maintaining the shrunk down entries
-            // using remove first, then insert will cause us to lose existing entries
-            let _ = self
-                .entries
-                .remove_overlapping(ie(new_region_start_page, new_region_end_page));
-        }
-
-        Ok(())
+        self.record(op, &result);
+        self.debug_validate();
+        result
     }
 
-    fn change_prot(&mut self, page_num: u32, npages: u32, new_prot: i32) {
-        let new_region_end_page = page_num + npages;
-        let new_region_start_page = page_num;
-
-        let mut to_insert = Vec::new();
-
-        for (overlap_interval, entry) in self
-            .entries
-            .overlapping_mut(ie(new_region_start_page, new_region_end_page))
-        {
-            let mut ent_start = overlap_interval.start();
-            let ent_end = overlap_interval.end();
-
-            if ent_start < new_region_start_page && ent_end > new_region_start_page {
-                to_insert.push(ie(new_region_start_page, ent_end));
-                ent_start = new_region_start_page; // need to update incase next condition is true
-            }
-            if ent_start < new_region_end_page && ent_end > new_region_end_page {
-                to_insert.push(ie(ent_start, new_region_end_page));
-            } else {
-                entry.prot = new_prot;
-            }
-        }
-
-        for interval in to_insert {
-            let mut interval_val = self.entries.get_at_point(interval.start()).unwrap().clone();
-            interval_val.prot = new_prot;
-            let _ = self.entries.insert_overwrite(interval, interval_val);
-        }
+    fn change_prot(&mut self, page_num: u64, npages: u64, new_prot: i32) -> Result<(), io::Error> {
+        let result = self.change_entries_prot(page_num, npages, new_prot);
+        let op = VmmapOp::Protect {
+            page_num,
+            npages,
+            prot: new_prot,
+        };
+        self.record(op, &result);
+        self.debug_validate();
+        result
     }
 
-    fn check_existing_mapping(&self, page_num: u32, npages: u32, prot: i32) -> bool {
-        let region_end_page = page_num + npages;
+    fn check_existing_mapping(&self, page_num: u64, npages: u64, prot: i32) -> bool {
+        // empty and overflowing regions are never mapped
+        let Some(region_end_page) = page_num
+            .checked_add(npages)
+            .filter(|end_page| *end_page > page_num)
+        else {
+            return false;
+        };
         let region_interval = ie(page_num, region_end_page);
 
         // If no overlap, return false
@@ -191,79 +749,56 @@ impl VmmapOps for Vmmap {
             return false;
         }
 
+        // Bounds come from the interval keys
         let mut current_page = page_num;
-
-        // Iterate over overlapping intervals
-        for (_interval, entry) in self.entries.overlapping(region_interval) {
-            let ent_end_page = entry.page_num + entry.npages;
-            let flags = entry.maxprot;
-
-            // Case 1: Fully inside the existing entry
-            if entry.page_num <= current_page && region_end_page <= ent_end_page {
-                return (prot & !flags) == 0;
-            }
-
-            // Case 2: Overlaps with the current entry
-            if entry.page_num <= current_page && current_page < ent_end_page {
-                if (prot & !flags) != 0 {
-                    return false;
-                }
-                current_page = ent_end_page; // Move to the next region
+        for (interval, entry) in self.entries.overlapping(region_interval) {
+            // There's a gap (no backing store) before this entry
+            if current_page < interval.start() {
+                return false;
             }
-
-            // Case 3: If there's a gap (no backing store), return false
-            if current_page < entry.page_num {
+            if (prot & !entry.maxprot) != 0 {
                 return false;
             }
+            current_page = interval.end() + 1; // Move to the next region
         }
 
-        false
+        current_page >= region_end_page
     }
 
-    fn check_addr_mapping(&mut self, page_num: u32, npages: u32, prot: i32) -> Option<u32> {
-        let region_end_page = page_num + npages;
+    fn check_addr_mapping(&mut self, page_num: u64, npages: u64, prot: i32) -> Option<u64> {
+        let region_end_page = page_num
+            .checked_add(npages)
+            .filter(|end_page| *end_page > page_num)?;
 
         // First, check if the cached entry can be used
-        if let Some(ref cached_entry) = self.cached_entry {
-            let ent_end_page = cached_entry.page_num + cached_entry.npages;
-            let mut flags = cached_entry.prot;
-
-            // If the protection is not PROT_NONE, enforce PROT_READ
-            if flags & (PROT_EXEC | PROT_READ | PROT_WRITE) != PROT_NONE {
-                flags |= PROT_READ;
-            }
-
-            if cached_entry.page_num <= page_num && region_end_page <= ent_end_page {
-                if prot & !flags == 0 {
-                    return Some(ent_end_page); // Mapping found inside the cached entry
-                }
+        if let Some((cached_entry, flags)) = self.cached_lookup(page_num, region_end_page) {
+            if prot & !flags == 0 {
+                // Mapping found inside the cached entry
+                return Some(cached_entry.page_num + cached_entry.npages);
             }
         }
 
         // If no cached entry, check the overlapping regions in memory map
+        // Bounds come from the interval keys
         let mut current_page = page_num;
-        for (_, entry) in self.entries.overlapping(ie(page_num, region_end_page)) {
-            let ent_end_page = entry.page_num + entry.npages;
-            let mut flags = entry.prot;
-
-            // If the protection is not PROT_NONE, enforce PROT_READ
-            if flags & (PROT_EXEC | PROT_READ | PROT_WRITE) != PROT_NONE {
-                flags |= PROT_READ;
-            }
+        for (interval, entry) in self.entries.overlapping(ie(page_num, region_end_page)) {
+            let ent_start_page = interval.start();
+            let ent_end_page = interval.end() + 1;
+            let flags = accessible_prot(entry.prot);
 
-            if entry.page_num <= current_page && region_end_page <= ent_end_page {
+            if ent_start_page <= current_page && region_end_page <= ent_end_page {
                 // Mapping is fully inside the current entry
-                self.cached_entry = Some(entry.clone()); // Cache the entry
+                self.cached_entry = Some(self.entry_for_interval(*interval, entry)); // Cache the entry
                 if prot & !flags == 0 {
                     return Some(ent_end_page);
                 }
-            } else if entry.page_num <= current_page && current_page < ent_end_page {
+            } else if ent_start_page <= current_page && current_page < ent_end_page {
                 // Mapping overlaps with this entry
                 if prot & !flags != 0 {
                     return None;
                 }
                 current_page = ent_end_page; // Move to next region
-            } else if current_page < entry.page_num {
+            } else if current_page < ent_start_page {
                 // There's a gap between entries, return failure
                 return None;
             }
@@ -273,36 +808,69 @@ impl VmmapOps for Vmmap {
         None
     }
 
-    fn find_page(&self, page_num: u32) -> Option<&VmmapEntry> {
+    /// Mirrors the order in which Linux resolves a fault: first the address needs to be
+    /// covered by an entry (SEGV_MAPERR otherwise), then the entry's protection needs to
+    /// allow the access (SEGV_ACCERR otherwise), and finally file backed pages that lie
+    /// entirely past the end of the file raise SIGBUS.
+    ///
+    /// The entry is found and its protection interpreted the same way as in
+    /// `check_addr_mapping`, i.e. any accessible mapping is implicitly readable.
+    fn classify_fault(&self, addr: u64, access: AccessKind) -> FaultClass {
+        let page_num = self.geometry.addr_to_page(addr);
+
+        let Some((entry, flags)) = self.lookup(page_num) else {
+            return FaultClass::NotMapped;
+        };
+
+        if access_prot(access) & !flags != 0 {
+            return FaultClass::ProtectionViolation(entry.prot);
+        }
+
+        if let MemoryBackingType::FileDescriptor(_) = entry.backing {
+            // file_offset is relative to the start of the entry
+            let page_offset = self
+                .geometry
+                .pages_to_bytes(page_num - entry.page_num)
+                .and_then(|bytes| i64::try_from(bytes).ok())
+                .and_then(|bytes| entry.file_offset.checked_add(bytes));
+            if page_offset.is_none_or(|offset| offset >= entry.file_size) {
+                return FaultClass::BeyondFileEnd;
+            }
+        }
+
+        FaultClass::Allowed
+    }
+
+    fn find_page(&self, page_num: u64) -> Option<&VmmapEntry> {
         self.entries.get_at_point(page_num)
     }
 
-    fn find_page_mut(&mut self, page_num: u32) -> Option<&mut VmmapEntry> {
+    fn find_page_mut(&mut self, page_num: u64) -> Option<&mut VmmapEntry> {
         self.entries.get_at_point_mut(page_num)
     }
 
-    fn last_entry(&self) -> Option<(&Interval<u32>, &VmmapEntry)> {
+    fn last_entry(&self) -> Option<(&Interval<u64>, &VmmapEntry)> {
         self.entries.last_key_value()
     }
 
-    fn first_entry(&self) -> Option<(&Interval<u32>, &VmmapEntry)> {
+    fn first_entry(&self) -> Option<(&Interval<u64>, &VmmapEntry)> {
         self.entries.first_key_value()
     }
 
-    fn double_ended_iter(&self) -> impl DoubleEndedIterator<Item = (&Interval<u32>, &VmmapEntry)> {
+    fn double_ended_iter(&self) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &VmmapEntry)> {
         self.entries.iter()
     }
 
     fn double_ended_iter_mut(
         &mut self,
-    ) -> impl DoubleEndedIterator<Item = (&Interval<u32>, &mut VmmapEntry)> {
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &mut VmmapEntry)> {
         self.entries.iter_mut()
     }
 
     fn find_page_iter(
         &self,
-        page_num: u32,
-    ) -> impl DoubleEndedIterator<Item = (&Interval<u32>, &VmmapEntry)> {
+        page_num: u64,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &VmmapEntry)> {
         if let Some(last_entry) = self.last_entry() {
             self.entries.overlapping(ie(page_num, last_entry.0.end()))
         } else {
@@ -313,8 +881,8 @@ impl VmmapOps for Vmmap {
 
     fn find_page_iter_mut(
         &mut self,
-        page_num: u32,
-    ) -> impl DoubleEndedIterator<Item = (&Interval<u32>, &mut VmmapEntry)> {
+        page_num: u64,
+    ) -> impl DoubleEndedIterator<Item = (&Interval<u64>, &mut VmmapEntry)> {
         if let Some(last_entry) = self.last_entry() {
             self.entries
                 .overlapping_mut(ie(page_num, last_entry.0.end()))
@@ -324,118 +892,66 @@ impl VmmapOps for Vmmap {
         }
     }
 
-    fn find_space(&self, npages: u32) -> Option<Interval<u32>> {
-        let start = self.first_entry();
-        let end = self.last_entry();
-
-        if start == None || end == None {
-            return None;
-        } else {
-            let start_unwrapped = start.unwrap().0.start();
-            let end_unwrapped = end.unwrap().0.end();
-
-            let desired_space = npages + 1; // TODO: check if this is correct
-
-            for gap in self
-                .entries
-                .gaps_trimmed(ie(start_unwrapped, end_unwrapped))
-            {
-                if gap.end() - gap.start() >= desired_space {
-                    return Some(gap);
-                }
-            }
-        }
-
-        None
+    /// Searches the whole bounded address space, see `Vmmap::with_bounds`
+    fn find_space(&self, npages: u64) -> Option<Interval<u64>> {
+        self.find_space_above_hint(npages, self.start_page)
     }
 
-    fn find_space_above_hint(&self, npages: u32, hint: u32) -> Option<Interval<u32>> {
-        let start = hint;
-        let end = self.last_entry();
-
-        if end == None {
+    /// Returns exactly `npages` free pages at or above `hint`, never the whole gap
+    fn find_space_above_hint(&self, npages: u64, hint: u64) -> Option<Interval<u64>> {
+        if npages == 0 {
             return None;
-        } else {
-            let end_unwrapped = end.unwrap().0.end();
-
-            let desired_space = npages + 1; // TODO: check if this is correct
-
-            for gap in self.entries.gaps_trimmed(ie(start, end_unwrapped)) {
-                if gap.end() - gap.start() >= desired_space {
-                    return Some(gap);
-                }
-            }
         }
 
-        None
+        self.placement.place(&self.free_gaps(hint), npages, 1)
     }
 
-    fn find_map_space(&self, num_pages: u32, pages_per_map: u32) -> Option<Interval<u32>> {
-        let start = self.first_entry();
-        let end = self.last_entry();
-
-        if start == None || end == None {
-            return None;
-        } else {
-            let start_unwrapped = start.unwrap().0.start();
-            let end_unwrapped = end.unwrap().0.end();
-
-            let rounded_num_pages =
-                self.round_page_num_up_to_map_multiple(num_pages, pages_per_map);
-
-            for gap in self
-                .entries
-                .gaps_trimmed(ie(start_unwrapped, end_unwrapped))
-            {
-                let aligned_start_page =
-                    self.trunc_page_num_down_to_map_multiple(gap.start(), pages_per_map);
-                let aligned_end_page =
-                    self.round_page_num_up_to_map_multiple(gap.end(), pages_per_map);
-
-                let gap_size = aligned_end_page - aligned_start_page;
-                if gap_size >= rounded_num_pages {
-                    return Some(ie(aligned_end_page - rounded_num_pages, aligned_end_page));
-                }
-            }
-        }
-
-        None
+    /// Searches the whole bounded address space, see `Vmmap::with_bounds`
+    fn find_map_space(&self, num_pages: u64) -> Option<Interval<u64>> {
+        self.find_map_space_with_hint(num_pages, self.start_page)
     }
 
-    fn find_map_space_with_hint(
-        &self,
-        num_pages: u32,
-        pages_per_map: u32,
-        hint: u32,
-    ) -> Option<Interval<u32>> {
-        let start = hint;
-        let end = self.last_entry();
-
-        if end == None {
+    /// Returns `num_pages` rounded up to the map granularity of the geometry in free
+    /// pages, starting on a multiple of it at or above `hint`. The interval is fully
+    /// contained in a single gap, so it never spills into the neighbouring mappings.
+    fn find_map_space_with_hint(&self, num_pages: u64, hint: u64) -> Option<Interval<u64>> {
+        if num_pages == 0 {
             return None;
-        } else {
-            let end_unwrapped = end.unwrap().0.end();
-
-            let rounded_num_pages =
-                self.round_page_num_up_to_map_multiple(num_pages, pages_per_map);
-
-            for gap in self.entries.gaps_trimmed(ie(start, end_unwrapped)) {
-                let aligned_start_page =
-                    self.trunc_page_num_down_to_map_multiple(gap.start(), pages_per_map);
-                let aligned_end_page =
-                    self.round_page_num_up_to_map_multiple(gap.end(), pages_per_map);
-
-                let gap_size = aligned_end_page - aligned_start_page;
-                if gap_size >= rounded_num_pages {
-                    return Some(ie(aligned_end_page - rounded_num_pages, aligned_end_page));
-                }
-            }
         }
 
-        None
+        let rounded_num_pages = self.geometry.round_page_num_up_to_map_multiple(num_pages)?;
+
+        self.placement.place(
+            &self.free_gaps(hint),
+            rounded_num_pages,
+            self.geometry.pages_per_map(),
+        )
     }
 }
 
+/// Whether `upper`, mapped right after `lower`, continues the same mapping, so
+/// that the two can be joined into one entry
+fn continues(lower: &VmmapEntry, upper: &VmmapEntry, geometry: &VmmapGeometry) -> bool {
+    let file_offset = match lower.backing {
+        MemoryBackingType::FileDescriptor(_) | MemoryBackingType::SharedMemory(_) => geometry
+            .pages_to_bytes(lower.npages)
+            .and_then(|bytes| i64::try_from(bytes).ok())
+            .and_then(|bytes| lower.file_offset.checked_add(bytes)),
+        MemoryBackingType::Anonymous | MemoryBackingType::None => Some(lower.file_offset),
+    };
+    upper.page_num == lower.page_num + lower.npages
+        && file_offset == Some(upper.file_offset)
+        && upper.prot == lower.prot
+        && upper.maxprot == lower.maxprot
+        && upper.flags == lower.flags
+        && upper.removed == lower.removed
+        && upper.file_size == lower.file_size
+        && upper.cage_id == lower.cage_id
+        && upper.backing == lower.backing
+        && upper.locked == lower.locked
+        && upper.advice == lower.advice
+}
+
 #[cfg(test)]
 pub mod test_vmmap_util {
     pub fn create_default_vmmap() {}
@@ -444,8 +960,12 @@ pub mod test_vmmap_util {
 #[cfg(test)]
 mod tests {
     use nodit::interval::ie;
+    use nodit::Interval;
 
-    use crate::types::VmmapOps;
+    use crate::constants::{EFAULT, EINVAL, PAGESHIFT, PAGESIZE, PROT_READ, PROT_WRITE};
+    use crate::placement::{BestFit, FirstFit, NextFit, PlacementStrategy, Random, TopDown};
+    use crate::types::{AccessKind, FaultClass, IoVec, MemoryBackingType, VmmapGeometry, VmmapOps};
+    use crate::utils::SeededRng;
     use crate::vmmap_entries::test_vmmap_entry_util::*;
 
     use super::Vmmap;
@@ -511,9 +1031,15 @@ mod tests {
 
         assert!(add_overwritten_vmmap_entry.is_ok());
         assert_eq!(vmmap.entries.len(), 3);
-        assert_eq!(vmmap.entries.get_at_point(0), Some(&vmmap_entry_0_10));
+        // the pieces left of the first entry only describe their own pages
+        let mut vmmap_entry_0_5 = create_default_vmmap_entry();
+        vmmap_entry_0_5.npages = 5;
+        let mut vmmap_entry_8_10 = create_default_vmmap_entry();
+        vmmap_entry_8_10.page_num = 8;
+        vmmap_entry_8_10.npages = 2;
+        assert_eq!(vmmap.entries.get_at_point(0), Some(&vmmap_entry_0_5));
         assert_eq!(vmmap.entries.get_at_point(5), Some(&vmmap_entry_5_10));
-        assert_eq!(vmmap.entries.get_at_point(8), Some(&vmmap_entry_0_10));
+        assert_eq!(vmmap.entries.get_at_point(8), Some(&vmmap_entry_8_10));
         assert_eq!(vmmap.entries.get_at_point(10), None);
         // just checks to see if all values in range are allocated
         assert!(vmmap.entries.contains_interval(ie(0, 10)));
@@ -542,4 +1068,500 @@ mod tests {
         let remove_non_existant = vmmap.remove_entry(11, 1);
         assert!(remove_non_existant.is_ok());
     }
+
+    #[test]
+    fn test_classify_fault() {
+        let mut vmmap = Vmmap::new();
+
+        // pages 0-9 read only anonymous memory
+        let mut vmmap_entry = create_default_vmmap_entry();
+        vmmap_entry.prot = PROT_READ;
+        vmmap_entry.maxprot = PROT_READ | PROT_WRITE;
+        vmmap.add_entry(vmmap_entry).unwrap();
+
+        // pages 10-13 backed by a file that is only 2.5 pages long
+        let mut file_entry = create_default_vmmap_entry();
+        file_entry.page_num = 10;
+        file_entry.npages = 4;
+        file_entry.prot = PROT_READ | PROT_WRITE;
+        file_entry.maxprot = PROT_READ | PROT_WRITE;
+        file_entry.file_size = (PAGESIZE * 2 + PAGESIZE / 2) as i64;
+        file_entry.backing = MemoryBackingType::FileDescriptor(3);
+        vmmap.add_entry(file_entry).unwrap();
+
+        let page_addr = |page: u64| page * PAGESIZE as u64;
+
+        assert_eq!(
+            vmmap.classify_fault(page_addr(3) + 17, AccessKind::Read),
+            FaultClass::Allowed
+        );
+        assert_eq!(
+            vmmap.classify_fault(page_addr(3), AccessKind::Write),
+            FaultClass::ProtectionViolation(PROT_READ)
+        );
+        assert_eq!(
+            vmmap.classify_fault(page_addr(12) + 5, AccessKind::Write),
+            FaultClass::Allowed
+        );
+        assert_eq!(
+            vmmap.classify_fault(page_addr(13), AccessKind::Read),
+            FaultClass::BeyondFileEnd
+        );
+        assert_eq!(
+            vmmap.classify_fault(page_addr(13), AccessKind::Exec),
+            FaultClass::ProtectionViolation(PROT_READ | PROT_WRITE)
+        );
+        assert_eq!(
+            vmmap.classify_fault(page_addr(14), AccessKind::Read),
+            FaultClass::NotMapped
+        );
+        assert_eq!(
+            vmmap.classify_fault(u64::MAX, AccessKind::Read),
+            FaultClass::NotMapped
+        );
+
+        // the entry cached by check_addr_mapping is dropped once it is unmapped
+        assert_eq!(vmmap.check_addr_mapping(2, 4, PROT_READ), Some(10));
+        vmmap.remove_entry(0, 10).unwrap();
+        assert_eq!(
+            vmmap.classify_fault(page_addr(3), AccessKind::Read),
+            FaultClass::NotMapped
+        );
+    }
+
+    #[test]
+    fn test_find_space_within_bounds() {
+        // an empty vmmap should still be able to place mappings
+        let mut vmmap = Vmmap::with_bounds(16, 64);
+        let space = vmmap.find_space(4).unwrap();
+        assert_eq!(space.start(), 16);
+
+        let space = vmmap.find_map_space(4).unwrap();
+        assert!(space.start() >= 16 && space.end() < 64);
+
+        // space above the highest existing mapping is also considered
+        let mut vmmap_entry = create_default_vmmap_entry();
+        vmmap_entry.page_num = 16;
+        vmmap_entry.npages = 32;
+        vmmap.add_entry(vmmap_entry).unwrap();
+
+        let space = vmmap.find_space(4).unwrap();
+        assert_eq!((space.start(), space.end()), (48, 51));
+
+        // hints outside the bounds are clamped or rejected
+        assert_eq!(vmmap.find_space_above_hint(4, 0).unwrap().start(), 48);
+        assert!(vmmap.find_space_above_hint(4, 64).is_none());
+        assert!(vmmap.find_space(32).is_none());
+    }
+
+    #[test]
+    fn test_find_space_top_down() {
+        let mut vmmap = Vmmap::with_bounds(16, 256);
+        vmmap.set_top_down(200);
+
+        let mut vmmap_entry = create_default_vmmap_entry();
+        vmmap_entry.page_num = 190;
+        vmmap_entry.npages = 10;
+        vmmap.add_entry(vmmap_entry).unwrap();
+
+        // placed right below the highest mapping under the mmap base
+        let space = vmmap.find_space(4).unwrap();
+        assert_eq!((space.start(), space.end()), (186, 189));
+
+        // aligned placement stays inside the gap below the mapping
+        let space = vmmap.find_map_space(5).unwrap();
+        assert_eq!((space.start(), space.end()), (160, 175));
+
+        // nothing is placed at or above the mmap base
+        assert!(vmmap.find_space(175).is_none());
+    }
+
+    #[test]
+    fn test_find_space_randomized() {
+        let place = |seed: u64| {
+            let mut vmmap = Vmmap::with_bounds(0, 1024);
+            vmmap.set_randomized(seed);
+
+            let mut vmmap_entry = create_default_vmmap_entry();
+            vmmap_entry.page_num = 100;
+            vmmap_entry.npages = 50;
+            vmmap.add_entry(vmmap_entry).unwrap();
+
+            (0..8)
+                .map(|_| {
+                    let space = vmmap.find_map_space(20).unwrap();
+                    assert_eq!(space.start() % 16, 0);
+                    assert_eq!(space.end() + 1 - space.start(), 32);
+                    assert!(!vmmap.entries.overlaps(space));
+                    space.start()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // same seed gives the same placements, different seeds differ
+        assert_eq!(place(7), place(7));
+        assert_ne!(place(7), place(8));
+    }
+
+    #[test]
+    fn test_placement_strategies() {
+        // free gaps: [0, 10), [20, 24), [30, 64)
+        let mut vmmap = Vmmap::with_bounds(0, 64);
+        for (page_num, npages) in [(10, 10), (24, 6)] {
+            let mut vmmap_entry = create_default_vmmap_entry();
+            vmmap_entry.page_num = page_num;
+            vmmap_entry.npages = npages;
+            vmmap.add_entry(vmmap_entry).unwrap();
+        }
+
+        assert_eq!(vmmap.find_space(4).unwrap().start(), 0);
+
+        vmmap.set_placement(BestFit);
+        assert_eq!(vmmap.find_space(4).unwrap().start(), 20);
+        assert_eq!(vmmap.find_space(5).unwrap().start(), 0);
+
+        vmmap.set_placement(NextFit::new());
+        assert_eq!(vmmap.find_space(4).unwrap().start(), 0);
+        assert_eq!(vmmap.find_space(4).unwrap().start(), 4);
+        assert_eq!(vmmap.find_space(4).unwrap().start(), 20);
+        assert_eq!(vmmap.find_space(30).unwrap().start(), 30);
+        assert_eq!(vmmap.find_space(4).unwrap().start(), 60);
+        // wraps around once the top of the address space is reached
+        assert_eq!(vmmap.find_space(8).unwrap().start(), 0);
+    }
+
+    /// Checks that `space` is a valid answer to a placement request of `npages`
+    /// pages aligned to `align` at or above `hint`
+    fn assert_valid_placement(
+        vmmap: &Vmmap,
+        space: Interval<u64>,
+        npages: u64,
+        align: u64,
+        hint: u64,
+    ) {
+        assert_eq!(space.end() + 1 - space.start(), npages);
+        assert_eq!(space.start() % align, 0);
+        assert!(space.start() >= hint.max(vmmap.start_page));
+        assert!(space.end() < vmmap.end_page);
+        assert!(!vmmap.entries.overlaps(space));
+    }
+
+    /// Brute force check for whether any aligned slot is free at or above `hint`
+    fn has_free_slot(vmmap: &Vmmap, npages: u64, align: u64, hint: u64) -> bool {
+        let first = hint.max(vmmap.start_page).next_multiple_of(align);
+        (first..vmmap.end_page)
+            .step_by(align as usize)
+            .take_while(|start| start + npages <= vmmap.end_page)
+            .any(|start| !vmmap.entries.overlaps(ie(start, start + npages)))
+    }
+
+    #[test]
+    fn test_placement_never_overlaps() {
+        let strategies: [fn(u64) -> Box<dyn PlacementStrategy>; 5] = [
+            |_| Box::new(FirstFit),
+            |_| Box::new(BestFit),
+            |_| Box::new(NextFit::new()),
+            |seed| Box::new(TopDown::new(256 + seed % 256)),
+            |seed| Box::new(Random::new(seed)),
+        ];
+
+        let mut rng = SeededRng::new(0x5eed);
+        for seed in 0..200 {
+            // map granularity of 1 to 16 pages
+            let geometry = VmmapGeometry::new(PAGESHIFT, PAGESHIFT + rng.below(5) as u32);
+            let align = geometry.pages_per_map();
+            let mut vmmap = Vmmap::with_geometry(geometry, 8, 512);
+            vmmap.placement = strategies[seed as usize % strategies.len()](seed);
+
+            for _ in 0..rng.below(24) {
+                let page_num = rng.below(512);
+                let npages = 1 + rng.below(32);
+                let _ = vmmap.add_entry_with_override(
+                    page_num,
+                    npages,
+                    PROT_READ,
+                    PROT_READ,
+                    0,
+                    MemoryBackingType::Anonymous,
+                    0,
+                    0,
+                    1,
+                );
+            }
+
+            for _ in 0..32 {
+                let npages = 1 + rng.below(64);
+                let hint = rng.below(512);
+
+                let requests = [
+                    (vmmap.find_space(npages), npages, 1, 0),
+                    (vmmap.find_space_above_hint(npages, hint), npages, 1, hint),
+                    (
+                        vmmap.find_map_space(npages),
+                        npages.next_multiple_of(align),
+                        align,
+                        0,
+                    ),
+                    (
+                        vmmap.find_map_space_with_hint(npages, hint),
+                        npages.next_multiple_of(align),
+                        align,
+                        hint,
+                    ),
+                ];
+
+                for (space, expected_pages, align, hint) in requests {
+                    match space {
+                        Some(space) => {
+                            assert_valid_placement(&vmmap, space, expected_pages, align, hint)
+                        }
+                        // only top-down may miss slots, since it ignores space above its base
+                        None if seed as usize % strategies.len() != 3 => {
+                            assert!(!has_free_slot(&vmmap, expected_pages, align, hint))
+                        }
+                        None => {}
+                    }
+                }
+            }
+        }
+
+        let vmmap = Vmmap::new();
+        assert!(vmmap.find_space(0).is_none());
+        assert!(vmmap.find_map_space(0).is_none());
+        assert!(vmmap.find_map_space(u64::MAX).is_none());
+    }
+
+    #[test]
+    fn test_custom_geometry() {
+        // 16 KiB pages mapped at 64 KiB granularity
+        let geometry = VmmapGeometry::new(14, 16);
+        assert_eq!(geometry.page_size(), 16 * 1024);
+        assert_eq!(geometry.pages_per_map(), 4);
+        assert_eq!(geometry.bytes_to_pages(16 * 1024 + 1), 2);
+        assert_eq!(geometry.round_page_num_up_to_map_multiple(5), Some(8));
+        assert_eq!(geometry.trunc_page_num_down_to_map_multiple(5), 4);
+
+        let mut vmmap = Vmmap::with_geometry(geometry, 0, 1 << 18);
+        let mut vmmap_entry = create_default_vmmap_entry();
+        vmmap_entry.npages = 3;
+        vmmap_entry.prot = PROT_READ;
+        vmmap_entry.maxprot = PROT_READ;
+        vmmap.add_entry(vmmap_entry).unwrap();
+
+        // byte addresses are converted using the 16 KiB page size
+        let addr = geometry.page_to_addr(2).unwrap() + 4096;
+        assert_eq!(
+            vmmap.classify_fault(addr, AccessKind::Read),
+            FaultClass::Allowed
+        );
+        let addr = geometry.page_to_addr(3).unwrap();
+        assert_eq!(
+            vmmap.classify_fault(addr, AccessKind::Read),
+            FaultClass::NotMapped
+        );
+
+        let space = vmmap.find_map_space(1).unwrap();
+        assert_eq!((space.start(), space.end()), (4, 7));
+    }
+
+    #[test]
+    fn test_large_address_space() {
+        // 47-bit user address space of 4 KiB pages, well beyond 2^32 pages
+        let end_page = 1 << (47 - 12);
+        let mut vmmap = Vmmap::with_bounds(0, end_page);
+        vmmap.set_placement(TopDown::new(end_page));
+
+        let space = vmmap.find_space(16).unwrap();
+        assert_eq!((space.start(), space.end()), (end_page - 16, end_page - 1));
+
+        let mut vmmap_entry = create_default_vmmap_entry();
+        vmmap_entry.page_num = space.start();
+        vmmap_entry.npages = 16;
+        vmmap_entry.prot = PROT_READ;
+        vmmap_entry.maxprot = PROT_READ;
+        vmmap.add_entry(vmmap_entry).unwrap();
+
+        let addr = vmmap.geometry.page_to_addr(end_page - 1).unwrap();
+        assert_eq!(
+            vmmap.classify_fault(addr, AccessKind::Read),
+            FaultClass::Allowed
+        );
+        assert_eq!(
+            vmmap.classify_fault(addr + 4096, AccessKind::Read),
+            FaultClass::NotMapped
+        );
+        assert!(vmmap.check_existing_mapping(end_page - 16, 16, PROT_READ));
+        // and empty ones are never mapped
+        assert!(!vmmap.check_existing_mapping(end_page - 16, 0, PROT_READ));
+        assert_eq!(vmmap.check_addr_mapping(end_page - 16, 0, PROT_READ), None);
+
+        // ranges running past the last page are refused instead of wrapping around
+        assert!(!vmmap.check_existing_mapping(u64::MAX - 1, 4, PROT_READ));
+        assert_eq!(vmmap.check_addr_mapping(u64::MAX - 1, 4, PROT_READ), None);
+        let err = vmmap
+            .add_entry_with_override(
+                u64::MAX - 1,
+                4,
+                PROT_READ,
+                PROT_READ,
+                0,
+                MemoryBackingType::Anonymous,
+                0,
+                0,
+                0,
+            )
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(EINVAL));
+        let err = vmmap.change_prot(u64::MAX - 1, 4, PROT_READ).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(EINVAL));
+    }
+
+    #[test]
+    fn test_change_prot_splits_entries() {
+        let mut vmmap = Vmmap::new();
+        let mut vmmap_entry = create_default_vmmap_entry();
+        vmmap_entry.prot = PROT_READ;
+        vmmap_entry.maxprot = PROT_READ | PROT_WRITE;
+        vmmap.add_entry(vmmap_entry).unwrap();
+
+        vmmap.change_prot(2, 5, PROT_READ | PROT_WRITE).unwrap();
+
+        let pieces: Vec<_> = vmmap
+            .entries
+            .iter()
+            .map(|(interval, entry)| (interval.start(), interval.end() + 1, entry.prot))
+            .collect();
+        assert_eq!(
+            pieces,
+            [
+                (0, 2, PROT_READ),
+                (2, 7, PROT_READ | PROT_WRITE),
+                (7, 10, PROT_READ),
+            ]
+        );
+        // every piece describes its own pages, not those of the original mapping
+        assert!(vmmap.entries.iter().all(|(interval, entry)| {
+            entry.page_num == interval.start()
+                && entry.npages == interval.end() + 1 - interval.start()
+        }));
+
+        // the cached entry must not let a later check see stale protections
+        assert_eq!(vmmap.check_addr_mapping(3, 1, PROT_WRITE), Some(7));
+        vmmap.change_prot(0, 10, PROT_READ).unwrap();
+        assert_eq!(vmmap.check_addr_mapping(3, 1, PROT_WRITE), None);
+
+        // file offsets move along with the start of each piece
+        let mut vmmap = Vmmap::new();
+        let mut vmmap_entry = create_default_vmmap_entry();
+        vmmap_entry.maxprot = PROT_READ;
+        vmmap_entry.backing = MemoryBackingType::FileDescriptor(3);
+        vmmap_entry.file_offset = PAGESIZE as i64;
+        vmmap_entry.file_size = 16 * PAGESIZE as i64;
+        vmmap.add_entry(vmmap_entry).unwrap();
+
+        vmmap.change_prot(4, 2, PROT_READ).unwrap();
+        let offsets: Vec<_> = vmmap
+            .entries
+            .iter()
+            .map(|(_, entry)| entry.file_offset / PAGESIZE as i64)
+            .collect();
+        assert_eq!(offsets, [1, 5, 7]);
+    }
+    #[test]
+    fn test_validate_user_range() {
+        let mut vmmap = Vmmap::new();
+        let mut vmmap_entry = create_default_vmmap_entry();
+        vmmap_entry.prot = PROT_READ | PROT_WRITE;
+        vmmap_entry.maxprot = PROT_READ | PROT_WRITE;
+        vmmap.add_entry(vmmap_entry).unwrap();
+        vmmap.change_prot(5, 5, PROT_READ).unwrap();
+
+        let page_size = PAGESIZE as u64;
+        let errno = |result: Result<(), std::io::Error>| result.unwrap_err().raw_os_error();
+
+        // unaligned range spanning both entries
+        assert!(vmmap
+            .validate_user_range(page_size + 7, 6 * page_size, AccessKind::Read)
+            .is_ok());
+        assert_eq!(
+            errno(vmmap.validate_user_range(page_size + 7, 6 * page_size, AccessKind::Write)),
+            Some(EFAULT)
+        );
+        // ends exactly on the last byte of the writable entry
+        assert!(vmmap
+            .validate_user_range(page_size, 4 * page_size, AccessKind::Write)
+            .is_ok());
+        // runs one byte past the end of the mapping
+        assert_eq!(
+            errno(vmmap.validate_user_range(9 * page_size, page_size + 1, AccessKind::Read)),
+            Some(EFAULT)
+        );
+        assert!(vmmap
+            .validate_user_range(100 * page_size, 0, AccessKind::Write)
+            .is_ok());
+        assert_eq!(
+            errno(vmmap.validate_user_range(u64::MAX, 2, AccessKind::Read)),
+            Some(EFAULT)
+        );
+
+        let iovecs = [
+            IoVec {
+                iov_base: 0,
+                iov_len: 16,
+            },
+            IoVec {
+                iov_base: 6 * page_size,
+                iov_len: page_size,
+            },
+        ];
+        assert!(vmmap
+            .validate_user_iovecs(&iovecs, AccessKind::Read)
+            .is_ok());
+        assert_eq!(
+            errno(vmmap.validate_user_iovecs(&iovecs, AccessKind::Write)),
+            Some(EFAULT)
+        );
+
+        let overflowing = [
+            IoVec {
+                iov_base: 0,
+                iov_len: u64::MAX,
+            },
+            IoVec {
+                iov_base: 0,
+                iov_len: 1,
+            },
+        ];
+        assert_eq!(
+            errno(vmmap.validate_user_iovecs(&overflowing, AccessKind::Read)),
+            Some(EINVAL)
+        );
+    }
+
+    #[test]
+    fn test_check_existing_mapping_across_holes() {
+        let mut vmmap = Vmmap::new();
+        for (page_num, npages) in [(0, 10), (8, 4)] {
+            vmmap
+                .add_entry_with_override(
+                    page_num,
+                    npages,
+                    PROT_READ,
+                    PROT_READ,
+                    0,
+                    MemoryBackingType::Anonymous,
+                    0,
+                    0,
+                    1,
+                )
+                .unwrap();
+        }
+        vmmap.remove_entry(4, 2).unwrap();
+
+        assert!(vmmap.check_existing_mapping(0, 4, PROT_READ));
+        assert!(vmmap.check_existing_mapping(6, 6, PROT_READ));
+        assert!(!vmmap.check_existing_mapping(0, 8, PROT_READ));
+        assert!(!vmmap.check_existing_mapping(2, 10, PROT_READ));
+        assert!(!vmmap.check_existing_mapping(6, 6, PROT_WRITE));
+    }
 }
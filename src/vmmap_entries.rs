@@ -4,13 +4,14 @@ use crate::constants::{
     PROT_NONE,
     // PROT_READ, PROT_WRITE,
 };
-use crate::types::{MemoryBackingType, VmmapEntry};
+use crate::types::{Advice, MemoryBackingType, VmmapEntry};
 
 #[allow(dead_code)]
 impl VmmapEntry {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        page_num: u32,
-        npages: u32,
+        page_num: u64,
+        npages: u64,
         prot: i32,
         maxprot: i32,
         flags: i32,
@@ -20,7 +21,7 @@ impl VmmapEntry {
         cage_id: u64,
         backing: MemoryBackingType,
     ) -> Self {
-        return VmmapEntry {
+        VmmapEntry {
             page_num,
             npages,
             prot,
@@ -31,7 +32,10 @@ impl VmmapEntry {
             file_size,
             cage_id,
             backing,
-        };
+            page_tracking: None,
+            locked: false,
+            advice: Advice::default(),
+        }
     }
 
     fn max_prot(&self) -> i32 {
@@ -0,0 +1,12 @@
+#![cfg(feature = "reference")]
+
+use rust_vmmap::reference::run_differential;
+
+#[test]
+fn vmmap_matches_page_array_reference() {
+    for seed in 0..32 {
+        if let Err(divergence) = run_differential(seed, 200) {
+            panic!("{divergence}");
+        }
+    }
+}